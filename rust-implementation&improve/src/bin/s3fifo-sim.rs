@@ -0,0 +1,204 @@
+//! Replays a trace through caches and prints their miss ratios, like libCacheSim's `cachesim`:
+//!
+//! ```text
+//! s3fifo-sim TRACE FORMAT ALGORITHMS SIZES [OPTIONS]
+//! ```
+//!
+//! Run it without arguments for the formats and options.
+
+use std::io;
+use std::num::NonZeroU32;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use s3fifo::sim::{self, CacheSize, SimError, Simulation, POLICIES};
+use s3fifo::trace::{
+    self, BlockOptions, CsvOptions, KeyHashing, TextOptions, TraceFormat, TwitterOptions,
+};
+
+const USAGE: &str = "\
+usage: s3fifo-sim TRACE FORMAT ALGORITHMS SIZES [OPTIONS]
+
+  TRACE       path to the trace, which may be zstd-compressed
+  FORMAT      oracleGeneral, csv, txt, twitter, msr or vscsi
+  ALGORITHMS  comma-separated, from: ALGORITHMS_LIST
+  SIZES       comma-separated cache sizes: bytes, with an optional KiB, MiB, GiB or TiB
+              suffix, or fractions of the working set such as 0.01
+
+options:
+  --ignore-obj-size   count objects rather than bytes; sizes are then numbers of objects
+  --threads N         simulate on N threads (default: one per core)
+  --hash-keys         hash all keys of text formats, even numeric ones
+  --obj-size N        size of every object in txt traces, and csv ones without a size column
+  --delimiter C       csv field delimiter (default ,)
+  --header            the csv trace starts with a header line
+  --key-col N         csv key column, from 0 (default 0)
+  --time-col N        csv timestamp column
+  --size-col N        csv object size column
+  --block-size N      split msr and vscsi I/Os into blocks of N bytes";
+
+struct Args {
+    trace: String,
+    format: TraceFormat,
+    algorithms: Vec<String>,
+    sizes: Vec<CacheSize>,
+    ignore_size: bool,
+    threads: Option<usize>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut ignore_size = false;
+    let mut key_hashing = KeyHashing::Auto;
+    let mut csv = CsvOptions::default();
+    let mut block_size = None;
+    let mut threads = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "--ignore-obj-size" => ignore_size = true,
+            "--hash-keys" => key_hashing = KeyHashing::Hash,
+            "--header" => csv.has_header = true,
+            "--obj-size" => csv.default_size = number(&value(&arg)?, &arg)?,
+            "--delimiter" => {
+                let delimiter = value(&arg)?;
+                let mut chars = delimiter.chars();
+                csv.delimiter = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => {
+                        return Err(format!(
+                            "--delimiter must be one character, not {delimiter:?}"
+                        ))
+                    }
+                };
+            }
+            "--key-col" => csv.key_column = number(&value(&arg)?, &arg)?,
+            "--time-col" => csv.time_column = Some(number(&value(&arg)?, &arg)?),
+            "--size-col" => csv.size_column = Some(number(&value(&arg)?, &arg)?),
+            "--threads" => threads = Some(number(&value(&arg)?, &arg)?),
+            "--block-size" => block_size = Some(number::<NonZeroU32>(&value(&arg)?, &arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            _ => positional.push(arg),
+        }
+    }
+    let [trace, format, algorithms, sizes] = <[String; 4]>::try_from(positional)
+        .map_err(|found| format!("expected 4 arguments, found {}", found.len()))?;
+
+    csv.key_hashing = key_hashing;
+    let block = BlockOptions { block_size };
+    let format = match format.to_ascii_lowercase().as_str() {
+        "oraclegeneral" => TraceFormat::OracleGeneral,
+        "csv" => TraceFormat::Csv(csv),
+        "txt" => TraceFormat::Text(TextOptions {
+            key_hashing,
+            size: csv.default_size,
+        }),
+        "twitter" => TraceFormat::Twitter(TwitterOptions {
+            key_hashing,
+            ..TwitterOptions::default()
+        }),
+        "msr" => TraceFormat::Msr(block),
+        "vscsi" => TraceFormat::Vscsi(block),
+        _ => return Err(format!("unknown trace format {format:?}")),
+    };
+    let algorithms = algorithms.split(',').map(str::to_string).collect();
+    let sizes = sizes
+        .split(',')
+        .map(|size| size.parse().map_err(|e: SimError| e.to_string()))
+        .collect::<Result<_, _>>()?;
+    Ok(Args {
+        trace,
+        format,
+        algorithms,
+        sizes,
+        ignore_size,
+        threads,
+    })
+}
+
+fn number<T: FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{name} {value:?} is not a valid number"))
+}
+
+fn run(args: Args) -> Result<(), String> {
+    // Catch misspelt algorithms before spending time on the trace.
+    for algorithm in &args.algorithms {
+        if let Err(e @ SimError::UnknownPolicy(_)) = sim::build_policy(algorithm, 2) {
+            return Err(e.to_string());
+        }
+    }
+    let trace_error = |e: io::Error| format!("{}: {e}", args.trace);
+    let open = || trace::open_trace(&args.trace, args.format.clone()).map_err(trace_error);
+
+    // Other formats do not record next accesses, which only the optimal policies need. They
+    // are the one reason to hold the whole trace in memory.
+    let offline = args.algorithms.iter().any(|a| sim::needs_next_access(a));
+    let requests = match offline && !matches!(args.format, TraceFormat::OracleGeneral) {
+        true => Some(sim::annotate_next_access(open()?).map_err(trace_error)?),
+        false => None,
+    };
+    // Likewise the trace is only read an extra time for sizes relative to its working set.
+    let relative = args
+        .sizes
+        .iter()
+        .any(|size| matches!(size, CacheSize::Fraction(_)));
+    let mut working_set = 0;
+    if relative {
+        let counted = match &requests {
+            Some(requests) => sim::working_set(requests.iter().copied().map(Ok)),
+            None => sim::working_set(open()?),
+        };
+        let counted = counted.map_err(trace_error)?;
+        let (size, unit) = match args.ignore_size {
+            true => (counted.objects, "objects"),
+            false => (counted.bytes, "bytes"),
+        };
+        println!("{}: working set of {size} {unit}", args.trace);
+        working_set = size;
+    }
+    let capacities = args
+        .sizes
+        .iter()
+        .map(|size| size.resolve(working_set))
+        .collect::<Vec<_>>();
+    let mut simulation = Simulation::new(&args.algorithms, &capacities)
+        .map_err(|e| e.to_string())?
+        .ignore_size(args.ignore_size);
+    if let Some(threads) = args.threads {
+        simulation = simulation.threads(threads);
+    }
+    let matrix = match requests {
+        Some(requests) => simulation.run(requests.into_iter().map(Ok)),
+        None => simulation.run(open()?),
+    };
+    let matrix = matrix.map_err(trace_error)?;
+    for (algorithm, capacity, result) in matrix.iter() {
+        println!(
+            "{} {algorithm} cache size {capacity}, {} req, miss ratio {:.4}, byte miss ratio {:.4}",
+            args.trace,
+            result.requests,
+            result.miss_ratio(),
+            result.byte_miss_ratio()
+        );
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        eprintln!("{}", USAGE.replace("ALGORITHMS_LIST", &POLICIES.join(", ")));
+        return ExitCode::from(2);
+    }
+    let result = parse_args(args).and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("s3fifo-sim: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! Validated construction of [`S3Fifo`], [`ShardedS3Fifo`] and [`ConcurrentS3Fifo`].
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use crate::ghost::{Ghost, GhostCapacity, GhostStorage};
+use crate::stats::Counters;
+use crate::{
+    Clock, ConcurrentS3Fifo, EventSink, EvictionListener, EvictionReason, HeuristicPolicy, S3Fifo,
+    ShardedS3Fifo, SmallSizePolicy, StaticPolicy, SystemClock, Weigher, MAX_FREQ,
+};
+
+/// The paper sizes the small queue at 10% of the cache.
+pub const DEFAULT_SMALL_RATIO: f64 = 0.1;
+
+/// Why an [`S3FifoBuilder`] refused to build.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    /// The cache (or one of its shards) cannot hold even one entry in each of small and main.
+    CapacityTooSmall { capacity: usize },
+    /// The small queue ratio is not strictly between 0 and 1.
+    InvalidSmallRatio(f64),
+    /// An explicit small queue size does not leave room for both small and main.
+    InvalidSmallSize { small: usize, capacity: usize },
+    /// The adaptive bounds do not satisfy `0 < min <= small <= max < capacity`.
+    InvalidSmallBounds {
+        min: usize,
+        max: usize,
+        small: usize,
+        capacity: usize,
+    },
+    /// A ghost capacity ratio is negative or not a number.
+    InvalidGhostRatio(f64),
+    /// The sizing policy needs a ghost of main, but none was given a capacity.
+    NoMainGhost,
+    /// The frequency settings do not satisfy `1 <= promotion_threshold <= max_freq` and
+    /// `1 <= reinsertion_decrement <= max_freq`.
+    InvalidFrequency {
+        max_freq: u8,
+        promotion_threshold: u8,
+        reinsertion_decrement: u8,
+    },
+    /// A sharded cache was asked for zero shards.
+    NoShards,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::CapacityTooSmall { capacity } => {
+                write!(f, "capacity {capacity} is too small to split into small and main")
+            }
+            ConfigError::InvalidSmallRatio(ratio) => {
+                write!(f, "small queue ratio {ratio} is not between 0 and 1")
+            }
+            ConfigError::InvalidSmallSize { small, capacity } => {
+                write!(f, "small queue size {small} does not fit in capacity {capacity}")
+            }
+            ConfigError::InvalidSmallBounds {
+                min,
+                max,
+                small,
+                capacity,
+            } => write!(
+                f,
+                "adaptive small queue bounds {min}..={max} must be non-zero, contain the small \
+                 queue size {small} and stay below capacity {capacity}"
+            ),
+            ConfigError::InvalidGhostRatio(ratio) => {
+                write!(f, "ghost capacity ratio {ratio} is not a non-negative number")
+            }
+            ConfigError::InvalidFrequency {
+                max_freq,
+                promotion_threshold,
+                reinsertion_decrement,
+            } => write!(
+                f,
+                "promotion threshold {promotion_threshold} and reinsertion decrement \
+                 {reinsertion_decrement} must be between 1 and the frequency cap {max_freq}"
+            ),
+            ConfigError::NoMainGhost => {
+                write!(f, "the small queue sizing policy needs a main ghost capacity")
+            }
+            ConfigError::NoShards => write!(f, "a sharded cache needs at least one shard"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+enum SmallSize {
+    Ratio(f64),
+    Fixed(usize),
+}
+
+// Every cache (and every shard) gets its own policy instance, made from the configured one.
+type PolicyFactory = Arc<dyn Fn() -> Box<dyn SmallSizePolicy> + Send + Sync>;
+
+/// Queue sizes resolved from the builder for one cache or shard.
+struct Sizes {
+    small: usize,
+    small_min: usize,
+    small_max: usize,
+    main: usize,
+    ghost: GhostSize,
+    main_ghost: GhostSize,
+}
+
+struct GhostSize {
+    size: usize,
+    per_entry: bool,
+}
+
+/// Configures an [`S3Fifo`]: a total capacity that is split between the small and main queues,
+/// optional bounds for adapting the small queue at run time, and the ghost capacity.
+///
+/// Sizes are entry counts unless a [`weigher`](S3FifoBuilder::weigher) is set, in which case
+/// they are budgets in the weigher's unit.
+pub struct S3FifoBuilder<K, V> {
+    capacity: usize,
+    small: SmallSize,
+    small_bounds: Option<(usize, usize)>,
+    ghost_capacity: GhostCapacity,
+    main_ghost_capacity: GhostCapacity,
+    ghost_storage: GhostStorage,
+    max_freq: u8,
+    promotion_threshold: u8,
+    reinsertion_decrement: u8,
+    policy: Option<PolicyFactory>,
+    weigher: Option<Weigher<K, V>>,
+    preallocate: bool,
+    clock: Arc<dyn Clock>,
+    listener: Option<EvictionListener<K, V>>,
+    sink: Option<Arc<dyn EventSink>>,
+}
+
+impl<K: Hash + Eq, V> S3FifoBuilder<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            small: SmallSize::Ratio(DEFAULT_SMALL_RATIO),
+            small_bounds: None,
+            ghost_capacity: GhostCapacity::MainRatio(1.0),
+            main_ghost_capacity: GhostCapacity::Entries(0),
+            ghost_storage: GhostStorage::Keys,
+            max_freq: MAX_FREQ,
+            promotion_threshold: 2,
+            reinsertion_decrement: 1,
+            policy: None,
+            weigher: None,
+            preallocate: true,
+            clock: Arc::new(SystemClock),
+            listener: None,
+            sink: None,
+        }
+    }
+
+    /// Gives the small queue this fraction of the capacity, 10% by default.
+    pub fn small_ratio(mut self, ratio: f64) -> Self {
+        self.small = SmallSize::Ratio(ratio);
+        self
+    }
+
+    /// Gives the small queue exactly this size, instead of a ratio of the capacity.
+    pub fn small_size(mut self, small: usize) -> Self {
+        self.small = SmallSize::Fixed(small);
+        self
+    }
+
+    /// Lets the small queue grow and shrink at run time between `min` and `max`, taking space
+    /// from main or giving it back. Without this the small queue keeps its initial size, as in
+    /// the paper.
+    pub fn adaptive_small(mut self, min: usize, max: usize) -> Self {
+        self.small_bounds = Some((min, max));
+        self
+    }
+
+    /// Resizes the small queue with `policy` within the adaptive bounds. Defaults to
+    /// [`HeuristicPolicy`] when bounds are set, and [`StaticPolicy`] otherwise.
+    pub fn small_size_policy<P: SmallSizePolicy + Clone + 'static>(mut self, policy: P) -> Self {
+        self.policy = Some(Arc::new(move || Box::new(policy.clone())));
+        self
+    }
+
+    /// How much the ghost of small remembers; by default as much as main holds.
+    pub fn ghost_capacity(mut self, capacity: GhostCapacity) -> Self {
+        self.ghost_capacity = capacity;
+        self
+    }
+
+    /// Also remembers keys evicted from main, so that the sizing policy hears when main let a key
+    /// go too early. Off by default; see [`GhostFeedbackPolicy`](crate::GhostFeedbackPolicy).
+    pub fn main_ghost_capacity(mut self, capacity: GhostCapacity) -> Self {
+        self.main_ghost_capacity = capacity;
+        self
+    }
+
+    /// How the ghosts remember keys: the keys themselves by default, or fingerprints of them to
+    /// save memory on large keys at the cost of rare false ghost hits.
+    pub fn ghost_storage(mut self, storage: GhostStorage) -> Self {
+        self.ghost_storage = storage;
+        self
+    }
+
+    /// Caps how many reads an entry's access count remembers, 3 by default as in the paper's
+    /// two-bit counters.
+    pub fn max_freq(mut self, max_freq: u8) -> Self {
+        self.max_freq = max_freq;
+        self
+    }
+
+    /// How many reads in small earn an entry its promotion to main; 2 by default.
+    pub fn promotion_threshold(mut self, reads: u8) -> Self {
+        self.promotion_threshold = reads;
+        self
+    }
+
+    /// How much an entry's access count drops each time it is put back at the head of main; 1 by
+    /// default.
+    pub fn reinsertion_decrement(mut self, decrement: u8) -> Self {
+        self.reinsertion_decrement = decrement;
+        self
+    }
+
+    /// Switches the cache to weighted mode: every entry is charged `weigher(key, value)` against
+    /// the queue it lives in, and all sizes become budgets in the same unit (typically bytes)
+    /// rather than entry counts.
+    pub fn weigher(mut self, weigher: impl Fn(&K, &V) -> usize + Send + Sync + 'static) -> Self {
+        self.weigher = Some(Arc::new(weigher));
+        self
+    }
+
+    /// Whether to allocate the queues and index for a full cache up front, which is the default
+    /// for entry counts. Turn it off when the capacity is a budget for sizes given to
+    /// [`S3Fifo::insert_with_size`], which says nothing about how many entries will fit.
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Reads the time for entry deadlines from `clock` instead of the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Calls `listener` for every entry that leaves the cache, with the reason it left.
+    pub fn eviction_listener(
+        mut self,
+        listener: impl Fn(&K, &V, EvictionReason) + Send + Sync + 'static,
+    ) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Reports resizes and queue movements to `sink`. Nothing is reported by default. The shards
+    /// of a sharded cache all report to the same sink.
+    pub fn event_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    pub fn build(self) -> Result<S3Fifo<K, V>, ConfigError> {
+        let sizes = self.resolve(|total| total)?;
+        Ok(self.build_with(sizes))
+    }
+
+    /// Builds a [`ShardedS3Fifo`] whose shards split the configured capacity, explicit sizes and
+    /// bounds between them as evenly as possible. Every shard must be valid on its own.
+    pub fn build_sharded(self, shards: usize) -> Result<ShardedS3Fifo<K, V>, ConfigError> {
+        if shards == 0 {
+            return Err(ConfigError::NoShards);
+        }
+        let split = |total: usize, i: usize| total / shards + usize::from(i < total % shards);
+        let sizes = (0..shards)
+            .map(|i| self.resolve(|total| split(total, i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ShardedS3Fifo::from_shards(
+            sizes.into_iter().map(|sizes| self.build_with(sizes)).collect(),
+        ))
+    }
+
+    /// Builds a [`ConcurrentS3Fifo`] with the configured capacity, small queue size and frequency
+    /// settings. The concurrent cache counts entries, has no deadlines, listeners or adaptive
+    /// sizing, and its ghost always remembers as many keys as main holds, so the other settings
+    /// do not apply to it.
+    pub fn build_concurrent(self) -> Result<ConcurrentS3Fifo<K, V>, ConfigError>
+    where
+        K: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let sizes = self.resolve(|total| total)?;
+        Ok(ConcurrentS3Fifo::new(sizes.small, sizes.main).with_frequency(
+            self.max_freq,
+            self.promotion_threshold,
+            self.reinsertion_decrement,
+        ))
+    }
+
+    /// Works out and checks the queue sizes, with `share` mapping each configured total to the
+    /// part of it that this cache gets.
+    fn resolve(&self, share: impl Fn(usize) -> usize) -> Result<Sizes, ConfigError> {
+        let frequency_range = 1..=self.max_freq;
+        if !frequency_range.contains(&self.promotion_threshold)
+            || !frequency_range.contains(&self.reinsertion_decrement)
+        {
+            return Err(ConfigError::InvalidFrequency {
+                max_freq: self.max_freq,
+                promotion_threshold: self.promotion_threshold,
+                reinsertion_decrement: self.reinsertion_decrement,
+            });
+        }
+        let capacity = share(self.capacity);
+        if capacity < 2 {
+            return Err(ConfigError::CapacityTooSmall { capacity });
+        }
+        let small = match self.small {
+            SmallSize::Ratio(ratio) => {
+                if !(ratio > 0.0 && ratio < 1.0) {
+                    return Err(ConfigError::InvalidSmallRatio(ratio));
+                }
+                ((capacity as f64 * ratio).round() as usize).clamp(1, capacity - 1)
+            }
+            SmallSize::Fixed(small) => {
+                let small = share(small);
+                if small == 0 || small >= capacity {
+                    return Err(ConfigError::InvalidSmallSize { small, capacity });
+                }
+                small
+            }
+        };
+        let (small_min, small_max) = self
+            .small_bounds
+            .map_or((small, small), |(min, max)| (share(min), share(max)));
+        if small_min == 0 || small_min > small || small > small_max || small_max >= capacity {
+            return Err(ConfigError::InvalidSmallBounds {
+                min: small_min,
+                max: small_max,
+                small,
+                capacity,
+            });
+        }
+        let main = capacity - small;
+        let ghost = |capacity| match capacity {
+            GhostCapacity::Entries(size) => Ok(GhostSize {
+                size: share(size),
+                per_entry: true,
+            }),
+            GhostCapacity::Weight(size) => Ok(GhostSize {
+                size: share(size),
+                per_entry: false,
+            }),
+            GhostCapacity::MainRatio(ratio) if ratio >= 0.0 && ratio.is_finite() => Ok(GhostSize {
+                size: (main as f64 * ratio).round() as usize,
+                per_entry: false,
+            }),
+            GhostCapacity::MainRatio(ratio) => Err(ConfigError::InvalidGhostRatio(ratio)),
+        };
+        let main_ghost = ghost(self.main_ghost_capacity)?;
+        let needs_main_ghost = self
+            .policy
+            .as_ref()
+            .is_some_and(|policy| policy().needs_main_ghost());
+        if needs_main_ghost && main_ghost.size == 0 {
+            return Err(ConfigError::NoMainGhost);
+        }
+        Ok(Sizes {
+            small,
+            small_min,
+            small_max,
+            main,
+            ghost: ghost(self.ghost_capacity)?,
+            main_ghost,
+        })
+    }
+
+    fn build_with(&self, sizes: Sizes) -> S3Fifo<K, V> {
+        // Queues are only preallocated for entry counts; weighted sizes say nothing about how
+        // many entries will fit.
+        let preallocate = self.preallocate && self.weigher.is_none();
+        let prealloc = |size: usize| if preallocate { size } else { 0 };
+        let ghost = |ghost: GhostSize| {
+            let per_entry = ghost.per_entry && self.preallocate;
+            let prealloc = if per_entry { ghost.size } else { prealloc(ghost.size) };
+            Ghost::new(self.ghost_storage, ghost.size, ghost.per_entry, prealloc)
+        };
+        S3Fifo {
+            small: VecDeque::with_capacity(prealloc(sizes.small)),
+            main: VecDeque::with_capacity(prealloc(sizes.main)),
+            ghost: ghost(sizes.ghost),
+            main_ghost: ghost(sizes.main_ghost),
+            index: HashMap::with_capacity(prealloc(sizes.small + sizes.main)),
+            weigher: self.weigher.clone().unwrap_or_else(|| Arc::new(|_, _| 1)),
+            clock: self.clock.clone(),
+            next_deadline: None,
+            listener: self.listener.clone(),
+            sink: self.sink.clone(),
+            counters: Counters::default(),
+            small_weight: 0,
+            main_weight: 0,
+            small_size: sizes.small,
+            small_min_size: sizes.small_min,
+            small_max_size: sizes.small_max,
+            main_size: sizes.main,
+            max_freq: self.max_freq,
+            promotion_threshold: self.promotion_threshold,
+            reinsertion_decrement: self.reinsertion_decrement,
+            policy: match (&self.policy, self.small_bounds) {
+                (Some(policy), _) => policy(),
+                (None, Some(_)) => Box::new(HeuristicPolicy::default()),
+                (None, None) => Box::new(StaticPolicy),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GhostFeedbackPolicy;
+
+    fn sizes(q: &S3Fifo<u32, u32>) -> (usize, usize, usize, usize, usize) {
+        (q.small_size, q.small_min_size, q.small_max_size, q.main_size, q.ghost.size())
+    }
+
+    #[test]
+    fn defaults_follow_the_paper() {
+        let q = S3Fifo::<u32, u32>::builder(1_000).build().unwrap();
+        assert_eq!(sizes(&q), (100, 100, 100, 900, 900));
+
+        let q = S3Fifo::<u32, u32>::builder(1_000)
+            .small_ratio(0.2)
+            .adaptive_small(100, 400)
+            .ghost_capacity(GhostCapacity::Entries(50))
+            .build()
+            .unwrap();
+        assert_eq!(sizes(&q), (200, 100, 400, 800, 50));
+
+        let q = S3Fifo::<u32, u32>::builder(1_000)
+            .ghost_capacity(GhostCapacity::MainRatio(0.5))
+            .build()
+            .unwrap();
+        assert_eq!(sizes(&q).4, 450);
+    }
+
+    #[test]
+    fn preallocates_only_when_asked() {
+        let q = S3Fifo::<u32, u32>::builder(1_000).build().unwrap();
+        assert!(q.main.capacity() >= 900);
+
+        // A terabyte of capacity would otherwise be a terabyte's worth of queue slots.
+        let mut q = S3Fifo::<u32, u32>::builder(1 << 40).preallocate(false).build().unwrap();
+        assert_eq!((q.small.capacity(), q.main.capacity()), (0, 0));
+        q.insert_with_size(1, 1, 1 << 20);
+        assert_eq!(q.peek(&1), Some(&1));
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        let build = |b: S3FifoBuilder<u32, u32>| b.build().err();
+        assert_eq!(
+            build(S3Fifo::builder(1)),
+            Some(ConfigError::CapacityTooSmall { capacity: 1 })
+        );
+        assert_eq!(
+            build(S3Fifo::builder(10).small_ratio(1.0)),
+            Some(ConfigError::InvalidSmallRatio(1.0))
+        );
+        assert!(matches!(
+            build(S3Fifo::builder(10).small_ratio(f64::NAN)),
+            Some(ConfigError::InvalidSmallRatio(_))
+        ));
+        assert_eq!(
+            build(S3Fifo::builder(10).small_size(10)),
+            Some(ConfigError::InvalidSmallSize { small: 10, capacity: 10 })
+        );
+        assert_eq!(
+            build(S3Fifo::builder(100).adaptive_small(20, 5)),
+            Some(ConfigError::InvalidSmallBounds { min: 20, max: 5, small: 10, capacity: 100 })
+        );
+        assert_eq!(
+            build(S3Fifo::builder(100).adaptive_small(5, 100)),
+            Some(ConfigError::InvalidSmallBounds { min: 5, max: 100, small: 10, capacity: 100 })
+        );
+        assert_eq!(
+            build(S3Fifo::builder(100).ghost_capacity(GhostCapacity::MainRatio(-1.0))),
+            Some(ConfigError::InvalidGhostRatio(-1.0))
+        );
+        assert_eq!(
+            build(S3Fifo::builder(100).promotion_threshold(4)),
+            Some(ConfigError::InvalidFrequency {
+                max_freq: 3,
+                promotion_threshold: 4,
+                reinsertion_decrement: 1
+            })
+        );
+        assert!(build(S3Fifo::builder(100).max_freq(7).promotion_threshold(4)).is_none());
+        let feedback = || {
+            S3Fifo::builder(100)
+                .adaptive_small(5, 50)
+                .small_size_policy(GhostFeedbackPolicy::default())
+        };
+        assert_eq!(build(feedback()), Some(ConfigError::NoMainGhost));
+        assert!(build(feedback().main_ghost_capacity(GhostCapacity::MainRatio(0.5))).is_none());
+        assert!(matches!(
+            build(S3Fifo::builder(100).reinsertion_decrement(0)),
+            Some(ConfigError::InvalidFrequency { .. })
+        ));
+        assert_eq!(
+            S3Fifo::<u32, u32>::builder(100).build_sharded(0).err(),
+            Some(ConfigError::NoShards)
+        );
+        // Every shard has to be valid on its own.
+        assert_eq!(
+            S3Fifo::<u32, u32>::builder(100).build_sharded(64).err(),
+            Some(ConfigError::CapacityTooSmall { capacity: 1 })
+        );
+    }
+}
@@ -0,0 +1,509 @@
+//! A concurrent S3-FIFO in which hits never take a lock.
+//!
+//! This follows the scalability argument of the paper: a hit only bumps the entry's atomic
+//! frequency counter, and all queue movement happens on insert. The pieces are:
+//!
+//! * an index of fixed-size buckets, each an epoch-protected, copy-on-write vector of entries that
+//!   writers replace with a compare-and-swap, so readers only ever pin the epoch;
+//! * lock-free ring buffers ([`ArrayQueue`]) for the small and main FIFOs;
+//! * a ghost table of timestamped key hashes, where a hash is a member only while it is among the
+//!   last `main` hashes inserted, which makes the table FIFO-ordered without a queue.
+//!
+//! Replaced and removed entries are only flagged in the rings and skipped once they reach the tail,
+//! so they briefly count against their queue's size. Evicted entries are freed through
+//! `crossbeam-epoch` once no reader can still be looking at them.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize};
+use std::sync::Arc;
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned, Shared};
+use crossbeam_queue::ArrayQueue;
+
+use crate::MAX_FREQ;
+
+// Ghost slots are grouped so a fingerprint only ever has to be looked for in one cache line.
+const GHOST_BUCKET_SLOTS: usize = 8;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    hash: u64,
+    freq: AtomicU8,
+    in_main: AtomicBool,
+    // Set by whoever unlinks the node from the index; the rings drop such nodes when popped.
+    removed: AtomicBool,
+}
+
+type Bucket<K, V> = Vec<Arc<Node<K, V>>>;
+
+/// Keys and values must be `Send + Sync + 'static` because replaced buckets are dropped through
+/// the epoch collector, which may run that drop on any thread and at any later time, even after
+/// the cache itself is gone.
+pub struct ConcurrentS3Fifo<K: Send + Sync + 'static, V: Send + Sync + 'static> {
+    buckets: Box<[Atomic<Bucket<K, V>>]>,
+    small: ArrayQueue<Arc<Node<K, V>>>,
+    main: ArrayQueue<Arc<Node<K, V>>>,
+    ghost: GhostTable,
+    small_size: usize,
+    main_size: usize,
+    len: AtomicUsize,
+    hasher: RandomState,
+    // As in `S3Fifo`; see the builder's frequency settings.
+    max_freq: u8,
+    promotion_threshold: u8,
+    reinsertion_decrement: u8,
+}
+
+impl<K: Hash + Eq + Send + Sync + 'static, V: Send + Sync + 'static> ConcurrentS3Fifo<K, V> {
+    /// Creates a cache holding about `small` + `main` entries; the ghost remembers `main` keys.
+    /// Access counts follow the paper; see [`S3FifoBuilder::build_concurrent`] to change them.
+    ///
+    /// [`S3FifoBuilder::build_concurrent`]: crate::S3FifoBuilder::build_concurrent
+    pub fn new(small: usize, main: usize) -> Self {
+        let small = small.max(1);
+        let main = main.max(1);
+        // The rings get some slack over their nominal sizes so that a reinsertion in `evict_main`
+        // practically never finds the ring filled up by concurrent inserts.
+        Self {
+            buckets: (0..(small + main).next_power_of_two())
+                .map(|_| Atomic::null())
+                .collect(),
+            small: ArrayQueue::new(small * 2),
+            main: ArrayQueue::new(main * 2),
+            ghost: GhostTable::new(main),
+            small_size: small,
+            main_size: main,
+            len: AtomicUsize::new(0),
+            hasher: RandomState::new(),
+            max_freq: MAX_FREQ,
+            promotion_threshold: 2,
+            reinsertion_decrement: 1,
+        }
+    }
+
+    /// Replaces the frequency settings, which the builder has already checked.
+    pub(crate) fn with_frequency(
+        mut self,
+        max_freq: u8,
+        promotion_threshold: u8,
+        reinsertion_decrement: u8,
+    ) -> Self {
+        self.max_freq = max_freq;
+        self.promotion_threshold = promotion_threshold;
+        self.reinsertion_decrement = reinsertion_decrement;
+        self
+    }
+
+    /// Number of entries currently in the index.
+    pub fn len(&self) -> usize {
+        self.len.load(Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a copy of the value for `key`, bumping its frequency. Never blocks.
+    pub fn read(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let hash = self.hasher.hash_one(key);
+        let guard = &epoch::pin();
+        let bucket = self.bucket(hash).load(Acquire, guard);
+        // SAFETY: buckets are only freed through `defer_destroy`, and we hold a guard.
+        let node = unsafe { bucket.as_ref() }?
+            .iter()
+            .find(|node| node.hash == hash && node.key == *key)?;
+        // Saturate at the cap in one atomic step, so concurrent hits are not lost. The update
+        // only writes when there is something to count, so hot keys don't bounce cache lines.
+        let _ = node
+            .freq
+            .fetch_update(Relaxed, Relaxed, |n| (n < self.max_freq).then(|| n + 1));
+        Some(node.value.clone())
+    }
+
+    /// Inserts `value` under `key`. A resident key is replaced, keeping its frequency and queue.
+    pub fn insert(&self, key: K, value: V) {
+        let hash = self.hasher.hash_one(&key);
+        let node = Arc::new(Node {
+            key,
+            value,
+            hash,
+            freq: AtomicU8::new(0),
+            in_main: AtomicBool::new(false),
+            removed: AtomicBool::new(false),
+        });
+        let guard = &epoch::pin();
+        let old = self.update_bucket(hash, guard, |nodes| {
+            let old = nodes.iter().find(|n| n.key == node.key).cloned();
+            let mut new: Bucket<K, V> = nodes
+                .iter()
+                .filter(|n| n.key != node.key)
+                .cloned()
+                .collect();
+            new.push(node.clone());
+            Some((new, old))
+        });
+        let to_main = match old.flatten() {
+            Some(old) => {
+                old.removed.store(true, Release);
+                node.freq.store(old.freq.load(Relaxed), Relaxed);
+                old.in_main.load(Relaxed)
+            }
+            None => {
+                self.len.fetch_add(1, Relaxed);
+                self.ghost.take(hash)
+            }
+        };
+        if to_main {
+            node.in_main.store(true, Relaxed);
+            self.push_main(node);
+        } else {
+            self.push_small(node);
+        }
+    }
+
+    /// Removes `key`, returning a copy of its value if it was resident.
+    pub fn remove(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        let hash = self.hasher.hash_one(key);
+        let guard = &epoch::pin();
+        let old = self.update_bucket(hash, guard, |nodes| {
+            let old = nodes.iter().find(|n| n.key == *key)?.clone();
+            let new = nodes.iter().filter(|n| n.key != *key).cloned().collect();
+            Some((new, old))
+        })?;
+        old.removed.store(true, Release);
+        self.len.fetch_sub(1, Relaxed);
+        Some(old.value.clone())
+    }
+
+    fn push_small(&self, mut node: Arc<Node<K, V>>) {
+        while self.small.len() >= self.small_size && self.evict_small() {}
+        while let Err(rejected) = self.small.push(node) {
+            node = rejected;
+            self.evict_small();
+        }
+    }
+
+    fn push_main(&self, mut node: Arc<Node<K, V>>) {
+        while self.main.len() >= self.main_size && self.evict_main() {}
+        while let Err(rejected) = self.main.push(node) {
+            node = rejected;
+            self.evict_main();
+        }
+    }
+
+    /// Frees one slot in small, promoting or demoting its tail. Returns false if small was empty.
+    fn evict_small(&self) -> bool {
+        let Some(tail) = self.small.pop() else {
+            return false;
+        };
+        if tail.removed.load(Acquire) {
+            return true;
+        }
+        if tail.freq.load(Relaxed) >= self.promotion_threshold {
+            tail.in_main.store(true, Relaxed);
+            self.push_main(tail);
+        } else if self.unlink(&tail) {
+            self.ghost.insert(tail.hash);
+        }
+        true
+    }
+
+    /// Frees one slot in main, reinserting tails that were accessed. Returns false if main was
+    /// empty.
+    fn evict_main(&self) -> bool {
+        while let Some(tail) = self.main.pop() {
+            if tail.removed.load(Acquire) {
+                return true;
+            }
+            let freq = tail.freq.load(Relaxed);
+            if freq == 0 {
+                self.unlink(&tail);
+                return true;
+            }
+            tail.freq
+                .store(freq.saturating_sub(self.reinsertion_decrement), Relaxed);
+            if let Err(tail) = self.main.push(tail) {
+                // Concurrent inserts took the slot back; this tail has to go instead.
+                self.unlink(&tail);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Takes `node` out of the index if it is still there. Returns whether this call did so.
+    fn unlink(&self, node: &Arc<Node<K, V>>) -> bool {
+        let guard = &epoch::pin();
+        let unlinked = self
+            .update_bucket(node.hash, guard, |nodes| {
+                nodes.iter().any(|n| Arc::ptr_eq(n, node)).then(|| {
+                    let new = nodes.iter().filter(|n| !Arc::ptr_eq(n, node)).cloned().collect();
+                    (new, ())
+                })
+            })
+            .is_some();
+        if unlinked {
+            node.removed.store(true, Release);
+            self.len.fetch_sub(1, Relaxed);
+        }
+        unlinked
+    }
+
+    fn bucket(&self, hash: u64) -> &Atomic<Bucket<K, V>> {
+        &self.buckets[hash as usize & (self.buckets.len() - 1)]
+    }
+
+    /// Replaces the bucket for `hash` with what `update` derives from its current contents,
+    /// retrying until no other writer got in between. `update` returning `None` leaves the bucket
+    /// alone, and it may run several times, so it must not have side effects.
+    fn update_bucket<R>(
+        &self,
+        hash: u64,
+        guard: &Guard,
+        mut update: impl FnMut(&[Arc<Node<K, V>>]) -> Option<(Bucket<K, V>, R)>,
+    ) -> Option<R> {
+        let bucket = self.bucket(hash);
+        loop {
+            let current = bucket.load(Acquire, guard);
+            // SAFETY: see `read`.
+            let nodes = unsafe { current.as_ref() }.map_or(&[][..], |nodes| nodes.as_slice());
+            let (new, result) = update(nodes)?;
+            let new = if new.is_empty() {
+                Shared::null()
+            } else {
+                Owned::new(new).into_shared(guard)
+            };
+            match bucket.compare_exchange(current, new, AcqRel, Acquire, guard) {
+                Ok(_) => {
+                    if !current.is_null() {
+                        // SAFETY: `current` is unreachable now; readers that still see it are
+                        // pinned, so it is only dropped after they unpin.
+                        unsafe { guard.defer_destroy(current) };
+                    }
+                    return Some(result);
+                }
+                Err(_) => {
+                    if !new.is_null() {
+                        // SAFETY: `new` was never published.
+                        drop(unsafe { new.into_owned() });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<K: Send + Sync + 'static, V: Send + Sync + 'static> Drop for ConcurrentS3Fifo<K, V> {
+    fn drop(&mut self) {
+        for bucket in self.buckets.iter() {
+            // SAFETY: `&mut self` means no other thread can reach the buckets any more.
+            unsafe {
+                let current = bucket.load(Relaxed, epoch::unprotected());
+                if !current.is_null() {
+                    drop(current.into_owned());
+                }
+            }
+        }
+    }
+}
+
+/// Hashes of keys evicted from small, each stamped with the ghost insertion that added it. A hash
+/// counts as present while fewer than `capacity` insertions have happened since, so old entries
+/// age out in FIFO order and are simply overwritten later.
+struct GhostTable {
+    slots: Box<[GhostSlot]>,
+    clock: AtomicU64,
+    capacity: u64,
+}
+
+// Stamps are full 64-bit insertion counts, so they never wrap around and bring stale hashes back
+// to life; 0 marks an empty slot.
+#[derive(Default)]
+struct GhostSlot {
+    hash: AtomicU64,
+    stamp: AtomicU64,
+}
+
+impl GhostTable {
+    fn new(capacity: usize) -> Self {
+        // Twice the slots needed, so an unlucky bucket rarely pushes out a still-live fingerprint.
+        let buckets = (capacity * 2).div_ceil(GHOST_BUCKET_SLOTS).next_power_of_two();
+        Self {
+            slots: (0..buckets * GHOST_BUCKET_SLOTS).map(|_| GhostSlot::default()).collect(),
+            clock: AtomicU64::new(0),
+            capacity: capacity as u64,
+        }
+    }
+
+    fn age(&self, stamp: u64) -> u64 {
+        self.clock.load(Relaxed).saturating_sub(stamp)
+    }
+
+    fn is_live(&self, stamp: u64) -> bool {
+        stamp != 0 && self.age(stamp) < self.capacity
+    }
+
+    fn bucket(&self, hash: u64) -> &[GhostSlot] {
+        let buckets = self.slots.len() / GHOST_BUCKET_SLOTS;
+        let start = (hash as usize & (buckets - 1)) * GHOST_BUCKET_SLOTS;
+        &self.slots[start..start + GHOST_BUCKET_SLOTS]
+    }
+
+    fn insert(&self, hash: u64) {
+        let now = self.clock.fetch_add(1, Relaxed) + 1;
+        // Reuse an empty or aged-out slot, or failing that push out the oldest hash. Racing
+        // inserts may overwrite each other, or pair a hash with the other's stamp, which only
+        // makes the ghost forget a key a little early or late.
+        let bucket = self.bucket(hash);
+        let victim = bucket
+            .iter()
+            .max_by_key(|slot| {
+                let stamp = slot.stamp.load(Relaxed);
+                if self.is_live(stamp) {
+                    self.age(stamp)
+                } else {
+                    u64::MAX
+                }
+            })
+            .expect("ghost buckets are never empty");
+        victim.stamp.store(0, Relaxed);
+        victim.hash.store(hash, Relaxed);
+        victim.stamp.store(now, Release);
+    }
+
+    /// Consumes the entry for `hash`, returning whether it was present.
+    fn take(&self, hash: u64) -> bool {
+        self.bucket(hash).iter().any(|slot| {
+            let stamp = slot.stamp.load(Acquire);
+            self.is_live(stamp)
+                && slot.hash.load(Relaxed) == hash
+                && slot.stamp.compare_exchange(stamp, 0, Relaxed, Relaxed).is_ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use std::thread;
+
+    #[test]
+    fn ghost_hits_go_to_main() {
+        let cache = ConcurrentS3Fifo::<u32, u32>::new(2, 8);
+        for k in 0..4 {
+            cache.insert(k, k);
+        }
+        // 0 and 1 were pushed out of small unread, so they are remembered by the ghost only.
+        assert_eq!(cache.read(&0), None);
+        cache.insert(0, 0);
+        let guard = &epoch::pin();
+        let bucket = unsafe { cache.bucket(cache.hasher.hash_one(0)).load(Acquire, guard).deref() };
+        assert!(bucket.iter().any(|n| n.key == 0 && n.in_main.load(Relaxed)));
+        // The ghost hit was consumed.
+        assert!(!cache.ghost.take(cache.hasher.hash_one(0)));
+    }
+
+    #[test]
+    fn ghost_ages_out_in_fifo_order() {
+        let ghost = GhostTable::new(4);
+        for hash in 1..=6u64 {
+            ghost.insert(hash << 32 | hash);
+        }
+        assert!(!ghost.take(1 << 32 | 1));
+        assert!(!ghost.take(2 << 32 | 2));
+        assert!(ghost.take(3 << 32 | 3));
+        assert!(!ghost.take(3 << 32 | 3));
+
+        // Stamps do not wrap around, however many insertions later.
+        ghost.clock.fetch_add(u64::from(u32::MAX) - 1, Relaxed);
+        ghost.insert(7);
+        ghost.clock.fetch_add(1 << 32, Relaxed);
+        assert!(!ghost.take(4 << 32 | 4));
+        assert!(!ghost.take(7));
+    }
+
+    #[test]
+    fn frequency_settings() {
+        let cache = crate::S3Fifo::<u32, u32>::builder(10)
+            .small_size(2)
+            .max_freq(5)
+            .promotion_threshold(1)
+            .build_concurrent()
+            .unwrap();
+        for _ in 0..10 {
+            cache.read(&0);
+        }
+        cache.insert(0, 0);
+        for _ in 0..10 {
+            cache.read(&0);
+        }
+        let freq = |k: u32| {
+            let guard = &epoch::pin();
+            let bucket = cache.bucket(cache.hasher.hash_one(k)).load(Acquire, guard);
+            let bucket = unsafe { bucket.as_ref() }?;
+            let node = bucket.iter().find(|n| n.key == k)?;
+            Some((node.freq.load(Relaxed), node.in_main.load(Relaxed)))
+        };
+        assert_eq!(freq(0), Some((5, false)));
+        // A single read is enough to be promoted rather than sent to the ghost.
+        cache.insert(1, 1);
+        cache.read(&1);
+        for k in 2..5 {
+            cache.insert(k, k);
+        }
+        assert_eq!(freq(0), Some((5, true)));
+        assert_eq!(freq(1), Some((1, true)));
+        // Unread entries still are.
+        assert_eq!(freq(2), None);
+        assert!(cache.ghost.take(cache.hasher.hash_one(2)));
+    }
+
+    #[test]
+    fn concurrent_reads_and_writes() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ConcurrentS3Fifo<String, Vec<u8>>>();
+
+        let cache = ConcurrentS3Fifo::<u32, u32>::new(20, 200);
+        thread::scope(|s| {
+            for t in 0..8 {
+                let cache = &cache;
+                s.spawn(move || {
+                    let mut rng = rand::rngs::StdRng::seed_from_u64(t);
+                    for _ in 0..20_000 {
+                        let k = rng.gen_range(0..1_000);
+                        match rng.gen_range(0..10) {
+                            0..=5 => {
+                                if let Some(v) = cache.read(&k) {
+                                    assert_eq!(v, k);
+                                }
+                            }
+                            6..=8 => cache.insert(k, k),
+                            _ => {
+                                cache.remove(&k);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        // Every indexed entry sits in one of the rings, which hold at most twice their size.
+        assert!(cache.len() <= 2 * (20 + 200));
+        let guard = &epoch::pin();
+        let indexed: usize = cache
+            .buckets
+            .iter()
+            .map(|b| unsafe { b.load(Acquire, guard).as_ref() }.map_or(0, Vec::len))
+            .sum();
+        assert_eq!(indexed, cache.len());
+    }
+}
@@ -0,0 +1,48 @@
+//! Structured reports of what the queues do, for recording adaptivity behaviour.
+
+use std::sync::mpsc::Sender;
+
+use crate::QueueState;
+
+/// A queue movement or resize, as reported to an [`EventSink`]. Weights are entry counts unless
+/// the cache has a weigher.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheEvent {
+    /// The sizing policy changed the small queue size from `small_from`, and the main queue size
+    /// from `main_from` if it moves capacity between the two.
+    Resized { small_from: usize, main_from: usize },
+    /// A key remembered by a ghost was inserted again and admitted straight to main;
+    /// `main_ghost` says whether it was the ghost of main rather than of small.
+    GhostHit { weight: usize, main_ghost: bool },
+    /// An entry accessed while in small moved to main.
+    Promoted { weight: usize },
+    /// An entry left small without being accessed enough, and its key went to the ghost.
+    Demoted { weight: usize },
+    /// An accessed entry at the tail of main was put back at its head.
+    Reinserted { weight: usize },
+    /// An entry left main for lack of space.
+    Evicted { weight: usize },
+}
+
+/// Receives every [`CacheEvent`] together with the queue sizes just after it, e.g. to log them
+/// or send them elsewhere for offline analysis.
+///
+/// Implemented for closures, and for channel senders so that events can be consumed on another
+/// thread.
+pub trait EventSink: Send + Sync {
+    fn record(&self, event: CacheEvent, queues: &QueueState);
+}
+
+impl<F: Fn(CacheEvent, &QueueState) + Send + Sync> EventSink for F {
+    fn record(&self, event: CacheEvent, queues: &QueueState) {
+        self(event, queues)
+    }
+}
+
+impl EventSink for Sender<(CacheEvent, QueueState)> {
+    fn record(&self, event: CacheEvent, queues: &QueueState) {
+        // Nobody listening any more is not the cache's problem.
+        let _ = self.send((event, *queues));
+    }
+}
@@ -0,0 +1,430 @@
+//! FIFO queues of recently evicted keys, remembered without their values.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+use crate::{compact_queues, resident};
+
+/// How a ghost remembers the keys it holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GhostStorage {
+    /// The keys themselves, so membership is exact. Each remembered key keeps its allocation
+    /// alive.
+    #[default]
+    Keys,
+    /// 32-bit fingerprints of the keys, at 12 bytes per remembered key. A key that was never
+    /// evicted is taken for a remembered one with a probability of about one in 2^32 per
+    /// remembered key.
+    Fingerprints32,
+    /// 64-bit fingerprints, at 16 bytes per remembered key, for practically no false hits.
+    Fingerprints64,
+}
+
+/// How much a ghost remembers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GhostCapacity {
+    /// Up to this many keys, however much their entries weighed.
+    Entries(usize),
+    /// Up to this much total weight of the entries the keys had while resident, in the unit of
+    /// the weigher (e.g. bytes). The same as `Entries` without a weigher.
+    Weight(usize),
+    /// This fraction of the size of main, in main's unit.
+    MainRatio(f64),
+}
+
+/// Remembers up to `size` evicted keys or weight of them, forgetting the oldest first.
+pub(crate) struct Ghost<K> {
+    store: Store<K>,
+    // Whether `size` counts keys, so every key is charged 1 whatever it weighed.
+    per_entry: bool,
+}
+
+enum Store<K> {
+    Keys(KeyGhost<K>),
+    Fingerprints32(FingerprintGhost<u32>),
+    Fingerprints64(FingerprintGhost<u64>),
+}
+
+impl<K: Hash + Eq> Ghost<K> {
+    /// A ghost of `size` keys if `per_entry`, or else of `size` weight, with room for `prealloc`
+    /// keys up front.
+    pub fn new(storage: GhostStorage, size: usize, per_entry: bool, prealloc: usize) -> Self {
+        let store = match storage {
+            GhostStorage::Keys => Store::Keys(KeyGhost::new(size, prealloc)),
+            GhostStorage::Fingerprints32 => {
+                Store::Fingerprints32(FingerprintGhost::new(size, prealloc))
+            }
+            GhostStorage::Fingerprints64 => {
+                Store::Fingerprints64(FingerprintGhost::new(size, prealloc))
+            }
+        };
+        Self { store, per_entry }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        match &self.store {
+            Store::Keys(ghost) => ghost.contains(key),
+            Store::Fingerprints32(ghost) => ghost.contains(key),
+            Store::Fingerprints64(ghost) => ghost.contains(key),
+        }
+    }
+
+    /// Total weight (or number, if sized in entries) of the keys remembered; for fingerprints an
+    /// upper bound.
+    pub fn weight(&self) -> usize {
+        match &self.store {
+            Store::Keys(ghost) => ghost.weight,
+            Store::Fingerprints32(ghost) => ghost.weight(),
+            Store::Fingerprints64(ghost) => ghost.weight(),
+        }
+    }
+
+    /// Remembers `key` as the newest entry, forgetting the oldest ones to make room. A key that
+    /// is already remembered keeps its place.
+    pub fn push(&mut self, key: Arc<K>, weight: usize) {
+        let weight = if self.per_entry { 1 } else { weight };
+        match &mut self.store {
+            Store::Keys(ghost) => ghost.push(key, weight),
+            Store::Fingerprints32(ghost) => ghost.push(&*key, weight),
+            Store::Fingerprints64(ghost) => ghost.push(&*key, weight),
+        }
+    }
+
+    /// Forgets `key`, returning whether it was remembered.
+    pub fn remove(&mut self, key: &K) -> bool {
+        match &mut self.store {
+            Store::Keys(ghost) => ghost.remove(key),
+            Store::Fingerprints32(ghost) => ghost.remove(key),
+            Store::Fingerprints64(ghost) => ghost.remove(key),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn size(&self) -> usize {
+        match &self.store {
+            Store::Keys(ghost) => ghost.size,
+            Store::Fingerprints32(ghost) => ghost.size as usize,
+            Store::Fingerprints64(ghost) => ghost.size as usize,
+        }
+    }
+
+    /// The keys remembered, newest first. Fingerprints cannot be listed.
+    #[cfg(test)]
+    pub fn keys(&self) -> Vec<&K> {
+        match &self.store {
+            Store::Keys(ghost) => ghost
+                .queue
+                .iter()
+                .filter(|key| ghost.is_live(key))
+                .map(|key| &**key)
+                .collect(),
+            Store::Fingerprints32(_) | Store::Fingerprints64(_) => {
+                panic!("a ghost of fingerprints cannot list its keys")
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn assert_consistent(&self) {
+        if let Store::Keys(ghost) = &self.store {
+            ghost.assert_consistent();
+        }
+    }
+}
+
+/// A ghost that keeps the keys themselves.
+struct KeyGhost<K> {
+    queue: VecDeque<Arc<K>>,
+    // Membership of `queue`, so lookups do not scan it, along with the weight each key had while
+    // resident. Forgetting a key only unindexes it, as the cache does with removed entries.
+    index: HashMap<Arc<K>, usize>,
+    weight: usize,
+    size: usize,
+}
+
+impl<K: Hash + Eq> KeyGhost<K> {
+    fn new(size: usize, prealloc: usize) -> Self {
+        Self {
+            queue: VecDeque::with_capacity(prealloc),
+            index: HashMap::with_capacity(prealloc),
+            weight: 0,
+            size,
+        }
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    fn push(&mut self, key: Arc<K>, weight: usize) {
+        if self.size == 0 || weight > self.size || self.index.contains_key(&key) {
+            return;
+        }
+        while self.weight + weight > self.size {
+            let Some(old) = self.queue.pop_back() else {
+                break;
+            };
+            if self.is_live(&old) {
+                if let Some(old_weight) = self.index.remove(&old) {
+                    self.weight -= old_weight;
+                }
+            }
+        }
+        self.index.insert(key.clone(), weight);
+        self.queue.push_front(key);
+        self.weight += weight;
+    }
+
+    fn remove(&mut self, key: &K) -> bool {
+        let Some(weight) = self.index.remove(key) else {
+            return false;
+        };
+        self.weight -= weight;
+        compact_queues(&self.index, [&mut self.queue]);
+        true
+    }
+
+    /// Whether a queued key is still remembered, as opposed to forgotten since.
+    fn is_live(&self, key: &Arc<K>) -> bool {
+        resident(&self.index, key).is_some()
+    }
+
+    #[cfg(test)]
+    fn assert_consistent(&self) {
+        assert_eq!(self.queue.iter().filter(|key| self.is_live(key)).count(), self.index.len());
+        assert_eq!(self.index.values().sum::<usize>(), self.weight);
+    }
+}
+
+const BUCKET_SLOTS: usize = 8;
+
+/// A fingerprint width for [`FingerprintGhost`]. Zero marks an empty slot, so no key hashes to
+/// it.
+trait Fingerprint: Copy + Default + Eq {
+    fn from_hash(hash: u64) -> Self;
+    fn bits(self) -> u64;
+}
+
+impl Fingerprint for u32 {
+    fn from_hash(hash: u64) -> Self {
+        ((hash >> 32) as u32).max(1)
+    }
+
+    fn bits(self) -> u64 {
+        u64::from(self)
+    }
+}
+
+impl Fingerprint for u64 {
+    fn from_hash(hash: u64) -> Self {
+        hash.max(1)
+    }
+
+    fn bits(self) -> u64 {
+        self
+    }
+}
+
+/// A ghost that keeps only fingerprints of the keys, in buckets of slots picked by the low bits
+/// of the fingerprint.
+///
+/// Instead of a queue, every slot records the running total of weight pushed before it. A slot
+/// is live while it and everything pushed after it fit in `size`, which forgets the oldest first
+/// just like a FIFO queue, and ages out in O(1) without ever visiting the slot. A removed key's
+/// share of the budget is not reused before it would have aged out.
+struct FingerprintGhost<F> {
+    hasher: RandomState,
+    fingerprints: Vec<F>,
+    pushed_at: Vec<u64>,
+    pushed: u64,
+    size: u64,
+}
+
+impl<F: Fingerprint> FingerprintGhost<F> {
+    fn new(size: usize, prealloc: usize) -> Self {
+        // Keep the table half empty for `prealloc` keys: pushing into a full bucket means
+        // rehashing every slot into a table twice the size.
+        let buckets = (prealloc * 2).div_ceil(BUCKET_SLOTS).next_power_of_two();
+        Self {
+            hasher: RandomState::new(),
+            fingerprints: vec![F::default(); buckets * BUCKET_SLOTS],
+            pushed_at: vec![0; buckets * BUCKET_SLOTS],
+            pushed: 0,
+            size: size as u64,
+        }
+    }
+
+    fn weight(&self) -> usize {
+        self.pushed.min(self.size) as usize
+    }
+
+    fn fingerprint<K: Hash>(&self, key: &K) -> F {
+        F::from_hash(self.hasher.hash_one(key))
+    }
+
+    fn bucket(&self, fingerprint: F) -> std::ops::Range<usize> {
+        let buckets = self.fingerprints.len() / BUCKET_SLOTS;
+        let start = (fingerprint.bits() as usize & (buckets - 1)) * BUCKET_SLOTS;
+        start..start + BUCKET_SLOTS
+    }
+
+    fn is_live(&self, slot: usize) -> bool {
+        self.fingerprints[slot] != F::default() && self.pushed - self.pushed_at[slot] <= self.size
+    }
+
+    fn find(&self, fingerprint: F) -> Option<usize> {
+        self.bucket(fingerprint)
+            .find(|&slot| self.fingerprints[slot] == fingerprint && self.is_live(slot))
+    }
+
+    fn contains<K: Hash>(&self, key: &K) -> bool {
+        self.find(self.fingerprint(key)).is_some()
+    }
+
+    fn push<K: Hash>(&mut self, key: &K, weight: usize) {
+        let fingerprint = self.fingerprint(key);
+        // A key heavier than the budget would age out at once, taking everything else with it.
+        if self.size == 0 || weight as u64 > self.size || self.find(fingerprint).is_some() {
+            return;
+        }
+        let pushed_at = self.pushed;
+        self.pushed += weight as u64;
+        let slot = loop {
+            if let Some(slot) = self.bucket(fingerprint).find(|&slot| !self.is_live(slot)) {
+                break slot;
+            }
+            // Every slot in the bucket is live. Grow while that many live keys could fit in the
+            // budget, or else give up the oldest one early.
+            if self.fingerprints.len() < 4 * self.size as usize {
+                self.grow();
+            } else {
+                break self.bucket(fingerprint).min_by_key(|&slot| self.pushed_at[slot]).unwrap();
+            }
+        };
+        self.fingerprints[slot] = fingerprint;
+        self.pushed_at[slot] = pushed_at;
+    }
+
+    fn remove<K: Hash>(&mut self, key: &K) -> bool {
+        let Some(slot) = self.find(self.fingerprint(key)) else {
+            return false;
+        };
+        self.fingerprints[slot] = F::default();
+        true
+    }
+
+    /// Doubles the buckets. Each old bucket splits into two new ones, so the live slots always
+    /// fit.
+    fn grow(&mut self) {
+        let live = (0..self.fingerprints.len())
+            .filter(|&slot| self.is_live(slot))
+            .map(|slot| (self.fingerprints[slot], self.pushed_at[slot]))
+            .collect::<Vec<_>>();
+        let slots = self.fingerprints.len() * 2;
+        self.fingerprints = vec![F::default(); slots];
+        self.pushed_at = vec![0; slots];
+        for (fingerprint, pushed_at) in live {
+            let slot = self
+                .bucket(fingerprint)
+                .find(|&slot| self.fingerprints[slot] == F::default())
+                .unwrap();
+            self.fingerprints[slot] = fingerprint;
+            self.pushed_at[slot] = pushed_at;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_oldest_first() {
+        let mut ghost = KeyGhost::new(3, 0);
+        for k in 0..5u32 {
+            ghost.push(Arc::new(k), 1);
+        }
+        assert!(!ghost.contains(&1));
+        assert!((2..5).all(|k| ghost.contains(&k)));
+
+        // A forgotten key leaves a stale slot that must not be mistaken for its reinsertion.
+        assert!(ghost.remove(&2));
+        assert!(!ghost.remove(&2));
+        ghost.push(Arc::new(2), 1);
+        ghost.push(Arc::new(5), 1);
+        assert!(!ghost.contains(&3));
+        assert!(ghost.contains(&2) && ghost.contains(&4) && ghost.contains(&5));
+        assert_eq!((ghost.index.len(), ghost.weight), (3, 3));
+
+        for k in 0..1_000 {
+            ghost.push(Arc::new(k + 100), 1);
+            ghost.remove(&(k + 100));
+        }
+        assert!(ghost.queue.len() <= 2 * ghost.index.len() + 16);
+
+        let mut disabled = KeyGhost::new(0, 0);
+        disabled.push(Arc::new(1u32), 1);
+        assert!(!disabled.contains(&1));
+    }
+
+    fn fingerprints<F: Fingerprint>() {
+        // Starting from a single bucket, the table grows rather than forget keys still in budget.
+        let mut ghost = FingerprintGhost::<F>::new(1_000, 0);
+        for k in 0..100u32 {
+            ghost.push(&k, 1);
+        }
+        assert!((0..100).all(|k| ghost.contains(&k)));
+        assert!(ghost.fingerprints.len() > BUCKET_SLOTS);
+
+        let mut ghost = FingerprintGhost::<F>::new(100, 1_000);
+        for k in 0..1_000u32 {
+            ghost.push(&k, 1);
+        }
+        assert!((0..900).all(|k| !ghost.contains(&k)));
+        assert!((900..1_000).all(|k| ghost.contains(&k)));
+        assert_eq!(ghost.weight(), 100);
+
+        // Pushing a remembered key again does not refresh it.
+        ghost.push(&900, 1);
+        assert!(ghost.remove(&999));
+        assert!(!ghost.remove(&999));
+        ghost.push(&1_000, 1);
+        assert!(!ghost.contains(&900) && ghost.contains(&901) && ghost.contains(&1_000));
+
+        // Weights age out by budget, not by count.
+        let mut ghost = FingerprintGhost::<F>::new(10, 16);
+        ghost.push(&1, 6);
+        ghost.push(&2, 4);
+        assert!(ghost.contains(&1) && ghost.contains(&2));
+        ghost.push(&3, 1);
+        assert!(!ghost.contains(&1) && ghost.contains(&2) && ghost.contains(&3));
+    }
+
+    #[test]
+    fn fingerprints_32() {
+        fingerprints::<u32>();
+    }
+
+    #[test]
+    fn fingerprints_64() {
+        fingerprints::<u64>();
+    }
+
+    #[test]
+    fn rejects_keys_heavier_than_itself() {
+        let storages = [
+            GhostStorage::Keys,
+            GhostStorage::Fingerprints32,
+            GhostStorage::Fingerprints64,
+        ];
+        for storage in storages {
+            let mut ghost = Ghost::new(storage, 10, false, 16);
+            ghost.push(Arc::new(1u32), 4);
+            ghost.push(Arc::new(2), 11);
+            assert!(ghost.contains(&1) && !ghost.contains(&2), "{storage:?}");
+            assert_eq!(ghost.weight(), 4, "{storage:?}");
+        }
+    }
+}
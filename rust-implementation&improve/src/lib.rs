@@ -1,146 +1,585 @@
 //! Simple implementation of "S3-FIFO" from "FIFO Queues are ALL You Need for Cache Eviction" by
 //! Juncheng Yang, et al: https://jasony.me/publication/sosp23-s3fifo.pdf
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
 use std::sync::atomic::AtomicU8;
 use std::sync::atomic::Ordering::SeqCst;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod builder;
+mod clock;
+mod concurrent;
+mod events;
+mod ghost;
+pub mod policies;
+mod sharded;
+pub mod sim;
+mod sizing;
+mod stats;
+pub mod trace;
+
+pub use builder::{ConfigError, S3FifoBuilder, DEFAULT_SMALL_RATIO};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use concurrent::ConcurrentS3Fifo;
+pub use events::{CacheEvent, EventSink};
+pub use ghost::{GhostCapacity, GhostStorage};
+pub use policies::CachePolicy;
+pub use sharded::ShardedS3Fifo;
+pub use sizing::{
+    GhostFeedbackPolicy, GhostHitPolicy, HeuristicPolicy, QueueState, SizingEvent,
+    SmallSizePolicy, StaticPolicy,
+};
+pub use stats::{Stats, FREQ_BUCKETS};
+
+use ghost::Ghost;
+use stats::Counters;
 
 // The paper uses two bits to count accesses, for a max of 3. We use 8 bit atomics, but will limit
-// the count to the same value, to prevent wrap-arounds causing problems.
+// the count to the same value by default, to prevent wrap-arounds causing problems.
 const MAX_FREQ: u8 = 3;
 
-struct Entry<K, V> {
-    key: K,
+/// Which of the two resident FIFO queues an entry currently lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Queue {
+    Small,
+    Main,
+}
+
+struct Entry<V> {
     value: V,
     freq: AtomicU8,
+    queue: Queue,
+    weight: usize,
+    // When the entry stops being served, if it was inserted with a time-to-live.
+    deadline: Option<Instant>,
 }
 
-impl<K, V> Entry<K, V> {
-    pub fn new(key: K, value: V) -> Self {
+impl<V> Entry<V> {
+    pub fn new(value: V, queue: Queue, weight: usize, deadline: Option<Instant>) -> Self {
         Self {
-            key,
             value,
             freq: AtomicU8::new(0),
+            queue,
+            weight,
+            deadline,
         }
     }
+
+    fn is_expired(&self, clock: &dyn Clock) -> bool {
+        self.deadline.is_some_and(|deadline| clock.now() >= deadline)
+    }
 }
 
-pub struct S3Fifo<K: PartialEq, V> {
-    small: VecDeque<Entry<K, V>>,
-    main: VecDeque<Entry<K, V>>,
-    ghost: VecDeque<K>,
+/// Computes how much of a queue's budget an entry takes up, e.g. its size in bytes.
+pub type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+/// Why an entry left the cache, as reported to an [`EvictionListener`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EvictionReason {
+    /// Pushed out of small without being accessed enough to be promoted; the key is remembered
+    /// in the ghost.
+    Small,
+    /// Pushed out of main after its accesses had been used up by reinsertions.
+    Main,
+    /// Taken out by `remove`, `remove_if`, `forget` or `retain`.
+    Removed,
+    /// Its value was replaced by an insert of the same key.
+    Replaced,
+    /// An insert of the same key brought a value too large to cache, which was dropped as well.
+    TooLarge,
+    /// Its deadline passed.
+    Expired,
+}
+
+/// Called with every entry that leaves the cache, just before its value is dropped or handed
+/// back to the caller, e.g. to write back dirty values.
+pub type EvictionListener<K, V> = Arc<dyn Fn(&K, &V, EvictionReason) + Send + Sync>;
+
+pub struct S3Fifo<K: Hash + Eq, V> {
+    small: VecDeque<Arc<K>>,
+    main: VecDeque<Arc<K>>,
+    // Keys demoted from small, which go straight to main if they are inserted again. Either ghost
+    // may hold fingerprints rather than the keys; see `GhostStorage`.
+    ghost: Ghost<K>,
+    // Keys evicted from main, remembered only when the builder sizes this ghost. Inserting one
+    // again admits it back to main and tells the sizing policy that main was too small.
+    main_ghost: Ghost<K>,
+    // Every key resident in `small` or `main`, with its value and access count. Keys are shared
+    // between the index and the FIFO queues through an `Arc`, so only `Hash + Eq` is required of
+    // them rather than `Clone`. A queued key whose `Arc` is no longer the one in the index was
+    // removed while queued, and is skipped when it reaches the tail.
+    index: HashMap<Arc<K>, Entry<V>>,
+    weigher: Weigher<K, V>,
+    clock: Arc<dyn Clock>,
+    // No entry expires before this, so expired entries are only swept once it has passed.
+    next_deadline: Option<Instant>,
+    listener: Option<EvictionListener<K, V>>,
+    sink: Option<Arc<dyn EventSink>>,
+    counters: Counters,
+    // Total weight currently held by each queue. Without a weigher every entry weighs 1, so these
+    // are plain entry counts and the queue sizes below are counts as well. Only the builder sets
+    // the sizes.
+    small_weight: usize,
+    main_weight: usize,
     small_size: usize,
     small_min_size: usize,
     small_max_size: usize,
     main_size: usize,
-    insert_count: usize, // 跟踪插入次数
-    small_operated: bool, // 标记自上次调整以来是否有操作发生在small队列上
+    // Access counts are capped at `max_freq`. Entries leaving small are promoted once read
+    // `promotion_threshold` times, and entries at the tail of main are put back with their count
+    // lowered by `reinsertion_decrement` while it is non-zero.
+    max_freq: u8,
+    promotion_threshold: u8,
+    reinsertion_decrement: u8,
+    // Decides `small_size`, within `small_min_size..=small_max_size`.
+    policy: Box<dyn SmallSizePolicy>,
 }
 
-impl<K: PartialEq, V> S3Fifo<K, V> {
-    pub fn new(small: usize,small_min:usize,small_max:usize, main: usize,insert_count:usize,small_operated:bool) -> Self {
-        Self {
-            small: VecDeque::with_capacity(small),
-            main: VecDeque::with_capacity(main),
-            ghost: VecDeque::with_capacity(main),
-            small_size: small,
-            small_min_size:small_min,
-            small_max_size:small_max,
-            main_size: main,
-            insert_count: insert_count, // 跟踪插入次数
-            small_operated: small_operated, // 标记自上次调整以来是否有操作发生在small队列上
-        }
-    }
-
-    pub fn insert(&mut self, key: K, value: V) {
-        let operated_on_small = self.small.iter().any(|entry| &entry.key == &key);
-        //self.adjust_small_size();
-        if operated_on_small {
-            self.small_operated = true;
-        }
-        // This could be implemented using lock-free queues to not require &mut self, but that is
-        // left as an exercise to the reader.
-        if self.ghost.contains(&key) {
-            if self.main.len() >= self.main_size {
-                self.evict_main();
-            }
-            self.main.push_front(Entry::new(key, value));
-        } else {
-            if self.small.len() >= self.small_size {
-                self.evict_small();
+impl<K: Hash + Eq, V> S3Fifo<K, V> {
+    /// Starts configuring a cache that holds `capacity` entries (or weight) across small and
+    /// main; see [`S3FifoBuilder`].
+    pub fn builder(capacity: usize) -> S3FifoBuilder<K, V> {
+        S3FifoBuilder::new(capacity)
+    }
+
+    /// Counts of hits, misses and queue movements since creation or the last [`S3Fifo::reset_stats`].
+    pub fn stats(&self) -> Stats {
+        self.counters.snapshot()
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset();
+    }
+
+    /// Number of entries resident in small and main, including expired ones not yet dropped.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The combined size of small and main, in entries or weight.
+    pub fn capacity(&self) -> usize {
+        self.small_size + self.main_size
+    }
+
+    /// Inserts `value` under `key`, weighing it with the configured weigher.
+    ///
+    /// A key that is already resident has its value replaced where it is, keeping its access
+    /// count, and the previous value is returned. Only keys that are not resident are admitted
+    /// to small (or, on a ghost hit, to main) and count towards adaptive resizing.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let size = (self.weigher)(&key, &value);
+        self.insert_with_size(key, value, size)
+    }
+
+    /// Inserts `value` under `key`, charging it `size` instead of asking the weigher.
+    ///
+    /// Entries too large for the small queue are admitted straight to main, and entries too large
+    /// for main are not cached at all, so the largest entry the cache holds is main's budget
+    /// (90% of the capacity by default) rather than [`S3Fifo::capacity`]. A resident key given
+    /// such a value is dropped, reported as [`EvictionReason::TooLarge`], and its previous value
+    /// returned.
+    pub fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        self.insert_entry(key, value, size, None)
+    }
+
+    /// Inserts `value` under `key` so that it is served for `ttl` from now, and then treated as a
+    /// miss. Replacing the value of a resident key also replaces its deadline. Once expired, the
+    /// entry is dropped before any live entry is evicted to make room.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let size = (self.weigher)(&key, &value);
+        let deadline = self.clock.now() + ttl;
+        self.insert_entry(key, value, size, Some(deadline))
+    }
+
+    fn insert_entry(
+        &mut self,
+        key: K,
+        value: V,
+        size: usize,
+        deadline: Option<Instant>,
+    ) -> Option<V> {
+        if let Some(deadline) = deadline {
+            self.next_deadline = Some(self.next_deadline.map_or(deadline, |d| d.min(deadline)));
+        }
+        if self.index.get(&key).is_some_and(|entry| entry.is_expired(&*self.clock)) {
+            // An expired entry was already a miss; admit the key afresh.
+            self.remove_entry(&key, EvictionReason::Expired);
+        }
+        if let Some(entry) = self.index.get(&key) {
+            if size > self.main_size {
+                // Too large to cache at all, like a new entry of this size.
+                return self.remove_entry(&key, EvictionReason::TooLarge);
             }
-            self.small.push_front(Entry::new(key, value));
+            if entry.queue == Queue::Small && size > self.small_size {
+                return Some(self.move_to_main(key, value, size, deadline));
+            }
+        }
+        if let Some(entry) = self.index.get_mut(&key) {
+            let in_small = entry.queue == Queue::Small;
+            entry.deadline = deadline;
+            let previous = std::mem::replace(&mut entry.value, value);
+            let old = std::mem::replace(&mut entry.weight, size);
+            match entry.queue {
+                Queue::Small => self.small_weight = self.small_weight - old + size,
+                Queue::Main => self.main_weight = self.main_weight - old + size,
+            }
+            self.notify(&key, &previous, EvictionReason::Replaced);
+            // A heavier value may have pushed its queue over budget.
+            self.evict_small(0);
+            self.evict_main(0);
+            self.adjust_small_size(SizingEvent::Updated { in_small });
+            return Some(previous);
         }
-        self.insert_count += 1;
-        // 每三次插入操作后，检查是否需要调整队列大小
-        if self.insert_count >= 3 {
-            self.adjust_small_size();
-            self.insert_count = 0; // 重置插入计数
-            self.small_operated = false; // 重置操作标记
+        // See `ConcurrentS3Fifo` for a variant built on lock-free queues that does not require
+        // &mut self.
+        let ghost_hit = self.ghost.contains(&key);
+        let main_ghost_hit = !ghost_hit && self.main_ghost.contains(&key);
+        if ghost_hit || main_ghost_hit || size > self.small_size {
+            if size > self.main_size {
+                return None;
+            }
+            // The key is resident again, so neither ghost should keep answering for it.
+            if ghost_hit {
+                Counters::bump(&self.counters.ghost_hits);
+                self.ghost.remove(&key);
+            }
+            if main_ghost_hit {
+                self.main_ghost.remove(&key);
+            }
+            Counters::bump(&self.counters.inserts);
+            self.evict_main(size);
+            let key = Arc::new(key);
+            self.main.push_front(key.clone());
+            self.index.insert(key, Entry::new(value, Queue::Main, size, deadline));
+            self.main_weight += size;
+            if ghost_hit || main_ghost_hit {
+                self.emit(CacheEvent::GhostHit {
+                    weight: size,
+                    main_ghost: main_ghost_hit,
+                });
+            }
+        } else {
+            Counters::bump(&self.counters.inserts);
+            self.evict_small(size);
+            let key = Arc::new(key);
+            self.small.push_front(key.clone());
+            self.index.insert(key, Entry::new(value, Queue::Small, size, deadline));
+            self.small_weight += size;
+        }
+        self.adjust_small_size(if main_ghost_hit {
+            SizingEvent::MainGhostHit
+        } else {
+            SizingEvent::Admitted { ghost_hit }
+        });
+        None
+    }
+
+    /// Replaces the value of a key in small with one too large for small, moving it to main as a
+    /// new entry of that size would be, with its access count. Returns the previous value.
+    fn move_to_main(&mut self, key: K, value: V, size: usize, deadline: Option<Instant>) -> V {
+        // The key queued in small goes stale once the index holds a new `Arc` for it.
+        let (_, mut entry) = self.index.remove_entry(&key).unwrap();
+        self.small_weight -= entry.weight;
+        let previous = std::mem::replace(&mut entry.value, value);
+        self.notify(&key, &previous, EvictionReason::Replaced);
+        entry.queue = Queue::Main;
+        entry.weight = size;
+        entry.deadline = deadline;
+        self.evict_main(size);
+        let key = Arc::new(key);
+        self.main.push_front(key.clone());
+        self.index.insert(key, entry);
+        self.main_weight += size;
+        self.compact();
+        self.adjust_small_size(SizingEvent::Updated { in_small: true });
+        previous
     }
-}
 
+    /// Returns the value for `key` and counts the access, unless the key is absent or expired.
     pub fn read(&self, key: &K) -> Option<&V> {
-        if let Some(entry) = self.small.iter()
-            .chain(self.main.iter())
-            .find(|e| &e.key == key)
-        {
-            if entry.freq.fetch_add(1, SeqCst) + 1 > MAX_FREQ {
-                // Clamp it.
-                entry.freq.store(MAX_FREQ, SeqCst);
-            }
-            Some(&entry.value)
-        } else {
-            None
-        }
+        let Some(entry) = self.index.get(key).filter(|e| !e.is_expired(&*self.clock)) else {
+            Counters::bump(&self.counters.misses);
+            return None;
+        };
+        Counters::bump(&self.counters.hits);
+        // Saturate at the cap rather than wrap around.
+        let _ = entry
+            .freq
+            .fetch_update(SeqCst, SeqCst, |n| (n < self.max_freq).then(|| n + 1));
+        Some(&entry.value)
+    }
 
+    /// Returns the value for `key` without counting an access, unless the key is absent or
+    /// expired.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.index
+            .get(key)
+            .filter(|entry| !entry.is_expired(&*self.clock))
+            .map(|entry| &entry.value)
     }
 
-    fn evict_main(&mut self) {
-        while let Some(tail) = self.main.pop_back() {
-            let n = tail.freq.load(SeqCst);
-            if n > 0 {
-                tail.freq.store(n - 1, SeqCst);
+    /// Evicts from main until an entry of `weight` fits within its budget.
+    fn evict_main(&mut self, weight: usize) {
+        if self.main_weight + weight > self.main_size {
+            self.sweep_expired();
+        }
+        while self.main_weight + weight > self.main_size {
+            let Some(tail) = self.main.pop_back() else {
+                break;
+            };
+            let Some(entry) = resident(&self.index, &tail) else {
+                continue;
+            };
+            let n = entry.freq.load(SeqCst);
+            let reason = if entry.is_expired(&*self.clock) {
+                // Expired entries are dropped however often they were read.
+                EvictionReason::Expired
+            } else if n > 0 {
+                self.counters.record_freq(n);
+                entry.freq.store(n.saturating_sub(self.reinsertion_decrement), SeqCst);
+                let weight = entry.weight;
                 self.main.push_front(tail);
+                Counters::bump(&self.counters.reinsertions);
+                self.emit(CacheEvent::Reinserted { weight });
+                continue;
             } else {
-                break;
+                self.counters.record_freq(n);
+                Counters::bump(&self.counters.evictions);
+                EvictionReason::Main
+            };
+            if let Some(entry) = self.index.remove(&tail) {
+                self.main_weight -= entry.weight;
+                self.notify(&tail, &entry.value, reason);
+                if reason == EvictionReason::Main {
+                    self.main_ghost.push(tail, entry.weight);
+                    self.emit(CacheEvent::Evicted {
+                        weight: entry.weight,
+                    });
+                }
             }
         }
     }
 
-    fn evict_small(&mut self) {
-        if let Some(tail) = self.small.pop_back() {
-            if tail.freq.load(SeqCst) > 1 {
-                if self.main.len() >= self.main_size {
-                    self.evict_main();
+    /// Moves entries out of small, into main or the ghost, until an entry of `weight` fits within
+    /// its budget.
+    fn evict_small(&mut self, weight: usize) {
+        if self.small_weight + weight > self.small_size {
+            self.sweep_expired();
+        }
+        while self.small_weight + weight > self.small_size {
+            let Some(tail) = self.small.pop_back() else {
+                break;
+            };
+            let Some(entry) = resident(&self.index, &tail) else {
+                continue;
+            };
+            let entry_weight = entry.weight;
+            let n = entry.freq.load(SeqCst);
+            self.small_weight -= entry_weight;
+            if entry.is_expired(&*self.clock) {
+                // Neither promoted nor remembered in the ghost: the entry did not leave for lack
+                // of space.
+                if let Some(entry) = self.index.remove(&tail) {
+                    self.notify(&tail, &entry.value, EvictionReason::Expired);
+                }
+            } else if n >= self.promotion_threshold && entry_weight <= self.main_size {
+                self.counters.record_freq(n);
+                Counters::bump(&self.counters.promotions);
+                self.evict_main(entry_weight);
+                if let Some(entry) = self.index.get_mut(&tail) {
+                    entry.queue = Queue::Main;
                 }
                 self.main.push_front(tail);
+                self.main_weight += entry_weight;
+                self.emit(CacheEvent::Promoted {
+                    weight: entry_weight,
+                });
             } else {
-                if self.ghost.len() >= self.main_size {
-                    self.ghost.pop_back();
+                // Also taken by entries that outweigh the whole of main, which adaptive sizing may
+                // have shrunk since they were admitted to small.
+                self.counters.record_freq(n);
+                Counters::bump(&self.counters.demotions);
+                Counters::bump(&self.counters.evictions);
+                if let Some(entry) = self.index.remove(&tail) {
+                    self.notify(&tail, &entry.value, EvictionReason::Small);
                 }
-                self.ghost.push_front(tail.key);
+                self.ghost.push(tail, entry_weight);
+                self.emit(CacheEvent::Demoted {
+                    weight: entry_weight,
+                });
             }
         }
     }
 
-    fn adjust_small_size(&mut self) {
-        if self.should_increase_small() {
-            eprintln!("increase_small");
-            self.small_size = std::cmp::min(self.small_size + 1, self.small_max_size);
-        } else if self.should_decrease_small() {
-            eprintln!("decrease_small");
-            self.small_size = std::cmp::max(self.small_size - 1, self.small_min_size);
+    /// Removes `key` from small or main, returning its value if it was resident. The ghost keeps
+    /// any memory of the key; see [`S3Fifo::forget`] to drop that too.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.remove_entry(key, EvictionReason::Removed)
+    }
+
+    fn remove_entry(&mut self, key: &K, reason: EvictionReason) -> Option<V> {
+        let entry = self.index.remove(key)?;
+        match entry.queue {
+            Queue::Small => self.small_weight -= entry.weight,
+            Queue::Main => self.main_weight -= entry.weight,
+        }
+        self.notify(key, &entry.value, reason);
+        self.compact();
+        Some(entry.value)
+    }
+
+    /// Removes `key` if it is resident and `predicate` accepts its value.
+    pub fn remove_if(&mut self, key: &K, predicate: impl FnOnce(&V) -> bool) -> Option<V> {
+        if predicate(&self.index.get(key)?.value) {
+            self.remove(key)
+        } else {
+            None
         }
     }
-    // 定义何时增加small队列大小的条件
-    fn should_increase_small(&self) -> bool {
-        self.small.len() == self.small_size 
+
+    /// Removes `key` from the cache and from the ghosts, so a later insert is admitted to small as
+    /// if the key had never been seen.
+    pub fn forget(&mut self, key: &K) -> Option<V> {
+        self.ghost.remove(key);
+        self.main_ghost.remove(key);
+        self.remove(key)
+    }
+
+    /// Keeps only the resident entries for which `f` returns true.
+    pub fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        self.retain_entries(EvictionReason::Removed, |key, entry| f(key, &entry.value));
     }
 
-    // 定义何时减少small队列大小的条件
-    fn should_decrease_small(&self) -> bool {
-        self.small_operated && self.small_size > self.small_min_size
+    /// Drops every expired entry now, rather than when space is next needed. Returns how many
+    /// were dropped.
+    pub fn purge_expired(&mut self) -> usize {
+        let before = self.index.len();
+        let now = self.clock.now();
+        let mut next_deadline = None;
+        self.retain_entries(EvictionReason::Expired, |_, entry| match entry.deadline {
+            Some(deadline) if deadline <= now => false,
+            Some(deadline) => {
+                next_deadline = Some(next_deadline.map_or(deadline, |d: Instant| d.min(deadline)));
+                true
+            }
+            None => true,
+        });
+        self.next_deadline = next_deadline;
+        before - self.index.len()
+    }
+
+    /// Purges expired entries if any may have expired, so that live ones are not evicted while
+    /// expired ones still take up space. Sweeps the whole index, but at most once per deadline
+    /// that passes.
+    fn sweep_expired(&mut self) {
+        if self.next_deadline.is_some_and(|deadline| self.clock.now() >= deadline) {
+            self.purge_expired();
+        }
+    }
+
+    fn retain_entries(
+        &mut self,
+        reason: EvictionReason,
+        mut f: impl FnMut(&K, &Entry<V>) -> bool,
+    ) {
+        let small_weight = &mut self.small_weight;
+        let main_weight = &mut self.main_weight;
+        let listener = &self.listener;
+        self.index.retain(|key, entry| {
+            let keep = f(key, entry);
+            if !keep {
+                match entry.queue {
+                    Queue::Small => *small_weight -= entry.weight,
+                    Queue::Main => *main_weight -= entry.weight,
+                }
+                if let Some(listener) = listener {
+                    listener(key, &entry.value, reason);
+                }
+            }
+            keep
+        });
+        self.compact();
+    }
+
+    fn emit(&self, event: CacheEvent) {
+        if let Some(sink) = &self.sink {
+            sink.record(event, &self.queue_state());
+        }
+    }
+
+    fn queue_state(&self) -> QueueState {
+        QueueState {
+            small_size: self.small_size,
+            small_weight: self.small_weight,
+            small_min: self.small_min_size,
+            small_max: self.small_max_size,
+            main_size: self.main_size,
+            main_weight: self.main_weight,
+            ghost_weight: self.ghost.weight(),
+            main_ghost_weight: self.main_ghost.weight(),
+        }
+    }
+
+    fn notify(&self, key: &K, value: &V, reason: EvictionReason) {
+        if let Some(listener) = &self.listener {
+            listener(key, value, reason);
+        }
+    }
+
+    fn compact(&mut self) {
+        compact_queues(&self.index, [&mut self.small, &mut self.main]);
+    }
+
+    /// Tells the sizing policy about `event` and applies the small queue size it asks for.
+    fn adjust_small_size(&mut self, event: SizingEvent) {
+        let state = self.queue_state();
+        let size = self
+            .policy
+            .on_event(event, &state)
+            .clamp(self.small_min_size, self.small_max_size);
+        if size == self.small_size {
+            return;
+        }
+        if self.policy.conserves_capacity() {
+            // The bounds keep small below the total, so main never drops to zero.
+            self.main_size = self.small_size + self.main_size - size;
+        }
+        self.small_size = size;
+        self.emit(CacheEvent::Resized {
+            small_from: state.small_size,
+            main_from: state.main_size,
+        });
+        // Whichever queue shrank may now be over budget.
+        self.evict_small(0);
+        self.evict_main(0);
+    }
+}
+
+/// Looks up the entry for a queued key, provided the key was not removed since it was queued.
+fn resident<'a, K: Hash + Eq, T>(index: &'a HashMap<Arc<K>, T>, key: &Arc<K>) -> Option<&'a T> {
+    index
+        .get_key_value(key)
+        .filter(|(indexed, _)| Arc::ptr_eq(indexed, key))
+        .map(|(_, entry)| entry)
+}
+
+/// Drops the keys that were removed from `index` while queued. Removal leaves them in place for
+/// the tail to skip, which keeps it O(1), so this only sweeps once they outnumber the live keys
+/// and stays O(1) amortized.
+fn compact_queues<K: Hash + Eq, T, const N: usize>(
+    index: &HashMap<Arc<K>, T>,
+    queues: [&mut VecDeque<Arc<K>>; N],
+) {
+    if queues.iter().map(|queue| queue.len()).sum::<usize>() > 2 * index.len() + 16 {
+        for queue in queues {
+            queue.retain(|key| resident(index, key).is_some());
+        }
     }
 }
 
@@ -152,7 +591,7 @@ mod tests {
     #[test]
     fn it_works() {
         let mut rng = rand::rngs::StdRng::seed_from_u64(0);
-        let mut q = S3Fifo::<u32, u32>::new(4,2,8, 20,0,false);
+        let mut q = S3Fifo::<u32, u32>::builder(24).small_size(4).adaptive_small(2, 8).build().unwrap();
 
         let mut hit_rate = (0, 0);
         for i in 0 .. 10_000 {
@@ -169,7 +608,7 @@ mod tests {
                     }
                     None => {
                         eprintln!("miss");
-                        assert!( q.main.iter().chain(q.small.iter()).find(|e| e.key == k).is_none());
+                        assert!( q.main.iter().chain(q.small.iter()).find(|key| ***key == k).is_none());
                         hit_rate.1 += 1;
                     }
                 }
@@ -179,9 +618,585 @@ mod tests {
             }
             assert!(q.main.len() <= q.main_size);
             assert!(q.small.len() <= q.small_size);
-            assert!(q.ghost.len() <= q.main_size);
+            // The ghost is sized from main as built, and keeps that size as main is resized.
+            assert!(q.ghost.weight() <= q.ghost.size());
+            assert_eq!(q.index.len(), q.small.len() + q.main.len());
+            assert_eq!(q.capacity(), 24);
+            q.ghost.assert_consistent();
         }
         let (n, d) = hit_rate;
         println!("{n}/{d} = {}", (n as f64) / (d as f64));
     }
+
+    /// Live keys in small, main and the ghost, newest first.
+    fn queues(q: &S3Fifo<u32, u32>) -> [Vec<u32>; 3] {
+        let live = |keys: &VecDeque<Arc<u32>>| {
+            keys.iter().filter(|k| resident(&q.index, k).is_some()).map(|k| **k).collect()
+        };
+        [live(&q.small), live(&q.main), q.ghost.keys().into_iter().copied().collect()]
+    }
+
+    /// Replays a trace of inserts and reads on a cache with room for 2 entries in small and 4 in
+    /// main, checking `queues` after every step against the hand-computed expectation.
+    fn replay(trace: &[(&str, u32, [&[u32]; 3])]) -> S3Fifo<u32, u32> {
+        let mut q = S3Fifo::builder(6).small_size(2).build().unwrap();
+        for (step, &(op, k, expected)) in trace.iter().enumerate() {
+            match op {
+                "insert" => {
+                    q.insert(k, k);
+                }
+                "read" => {
+                    q.read(&k);
+                }
+                _ => unreachable!(),
+            }
+            assert_eq!(queues(&q), expected.map(<[u32]>::to_vec), "step {step}: {op} {k}");
+        }
+        q
+    }
+
+    #[test]
+    fn ghost_hits_are_consumed() {
+        let q = replay(&[
+            ("insert", 1, [&[1], &[], &[]]),
+            ("insert", 2, [&[2, 1], &[], &[]]),
+            // small -> ghost: 1 was never read.
+            ("insert", 3, [&[3, 2], &[], &[1]]),
+            // ghost -> main, taking 1 out of the ghost.
+            ("insert", 1, [&[3, 2], &[1], &[]]),
+            ("insert", 4, [&[4, 3], &[1], &[2]]),
+            ("insert", 5, [&[5, 4], &[1], &[3, 2]]),
+            ("insert", 2, [&[5, 4], &[2, 1], &[3]]),
+            ("insert", 3, [&[5, 4], &[3, 2, 1], &[]]),
+            ("insert", 6, [&[6, 5], &[3, 2, 1], &[4]]),
+            ("insert", 4, [&[6, 5], &[4, 3, 2, 1], &[]]),
+            ("insert", 7, [&[7, 6], &[4, 3, 2, 1], &[5]]),
+            // 1 leaves main for good...
+            ("insert", 5, [&[7, 6], &[5, 4, 3, 2], &[]]),
+            // ...so it starts over in small rather than skipping it on a stale ghost entry.
+            ("insert", 1, [&[1, 7], &[5, 4, 3, 2], &[6]]),
+        ]);
+        assert_eq!(q.stats().ghost_hits, 5);
+    }
+
+    #[test]
+    fn promotion_and_reinsertion() {
+        let q = replay(&[
+            ("insert", 1, [&[1], &[], &[]]),
+            ("read", 1, [&[1], &[], &[]]),
+            ("read", 1, [&[1], &[], &[]]),
+            ("insert", 2, [&[2, 1], &[], &[]]),
+            // small -> main: 1 was read twice.
+            ("insert", 3, [&[3, 2], &[1], &[]]),
+            // A single read is not enough to be promoted.
+            ("read", 2, [&[3, 2], &[1], &[]]),
+            ("insert", 4, [&[4, 3], &[1], &[2]]),
+            ("insert", 2, [&[4, 3], &[2, 1], &[]]),
+            ("insert", 5, [&[5, 4], &[2, 1], &[3]]),
+            ("insert", 3, [&[5, 4], &[3, 2, 1], &[]]),
+            ("insert", 6, [&[6, 5], &[3, 2, 1], &[4]]),
+            ("insert", 4, [&[6, 5], &[4, 3, 2, 1], &[]]),
+            ("insert", 7, [&[7, 6], &[4, 3, 2, 1], &[5]]),
+            // Main reinsertion: 1 still has reads left, so it goes back to the head and 2, unread
+            // since it came back from the ghost, is evicted instead.
+            ("insert", 5, [&[7, 6], &[5, 1, 4, 3], &[]]),
+        ]);
+        assert_eq!(q.index[&1].freq.load(SeqCst), 1);
+        assert_eq!(q.stats().reinsertions, 1);
+    }
+
+    #[test]
+    fn frequency_settings() {
+        let mut q = S3Fifo::<u32, u32>::builder(6)
+            .small_size(2)
+            .max_freq(7)
+            .promotion_threshold(1)
+            .reinsertion_decrement(3)
+            .build()
+            .unwrap();
+        q.insert(1, 1);
+        for _ in 0..10 {
+            q.read(&1);
+        }
+        assert_eq!(q.index[&1].freq.load(SeqCst), 7);
+        q.insert(2, 2);
+        q.read(&2);
+        for k in [3, 4, 5, 6, 3, 4, 7] {
+            q.insert(k, k); // 1 and 2 promoted on a single read, 3, 4 and 5 demoted
+        }
+        assert_eq!(queues(&q), [vec![7, 6], vec![4, 3, 2, 1], vec![5]]);
+        // 1 and 2 are both reinserted, 2 with its count floored at 0, and 3 is evicted.
+        q.insert(5, 5);
+        assert_eq!(queues(&q)[1], [5, 2, 1, 4]);
+        assert_eq!(q.index[&1].freq.load(SeqCst), 4);
+        assert_eq!(q.index[&2].freq.load(SeqCst), 0);
+        assert_eq!(q.stats().tail_freqs, [4, 2, 0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn upsert() {
+        let mut q = S3Fifo::<u32, &str>::builder(6).small_size(2).build().unwrap();
+        assert_eq!(q.insert(1, "a"), None);
+        q.read(&1);
+        q.read(&1);
+        assert_eq!(q.insert(1, "b"), Some("a"));
+        assert_eq!(q.read(&1), Some(&"b"));
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.small.len(), 1);
+        // The update is not a new admission and keeps the accesses made so far.
+        assert_eq!(q.stats().inserts, 1);
+        assert_eq!(q.index[&1].freq.load(SeqCst), 3);
+
+        // Being pushed out of small still promotes it.
+        q.insert(2, "c");
+        q.insert(3, "d");
+        assert_eq!(q.index[&1].queue, Queue::Main);
+        assert_eq!(q.insert(1, "e"), Some("b"));
+        assert_eq!(q.index[&1].queue, Queue::Main);
+    }
+
+    #[test]
+    fn expiration() {
+        let clock = Arc::new(ManualClock::new());
+        let mut q = S3Fifo::<u32, u32>::builder(6)
+            .small_size(2)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        q.insert_with_ttl(1, 1, Duration::from_secs(10));
+        q.insert(2, 2);
+        for _ in 0..3 {
+            q.read(&1);
+            q.read(&2);
+        }
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(q.read(&1), None);
+        assert_eq!(q.read(&2), Some(&2));
+
+        // The expired entry is neither promoted nor sent to the ghost on its way out of small.
+        q.insert(3, 3);
+        q.insert(4, 4);
+        assert!(!q.index.contains_key(&1));
+        assert!(!q.ghost.contains(&1));
+        assert_eq!(q.index[&2].queue, Queue::Main);
+
+        // Nor is it reinserted in main, however often it was read.
+        q.insert_with_ttl(2, 2, Duration::from_secs(5));
+        clock.advance(Duration::from_secs(5));
+        for k in 10..20 {
+            q.insert(k, k);
+            q.read(&k);
+            q.read(&k);
+        }
+        assert!(!q.index.contains_key(&2));
+
+        q.insert_with_ttl(30, 30, Duration::from_secs(1));
+        q.insert_with_ttl(31, 31, Duration::from_secs(2));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(q.purge_expired(), 1);
+        assert_eq!(q.read(&31), Some(&31));
+        // Reinserting an expired key starts it over rather than returning the stale value.
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(q.insert(31, 0), None);
+        assert_eq!(q.read(&31), Some(&0));
+    }
+
+    #[test]
+    fn expired_entries_make_room_first() {
+        let clock = Arc::new(ManualClock::new());
+        let mut q = S3Fifo::<u32, u32>::builder(6)
+            .small_size(3)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        q.insert(1, 1);
+        q.insert_with_ttl(2, 2, Duration::from_secs(1));
+        q.insert(3, 3);
+        clock.advance(Duration::from_secs(1));
+        // 2 is in the middle of small, but goes before 1 at the tail.
+        q.insert(4, 4);
+        assert!(!q.index.contains_key(&2));
+        assert_eq!(q.index[&1].queue, Queue::Small);
+        assert!(!q.ghost.contains(&1));
+        assert_eq!(q.stats().evictions, 0);
+    }
+
+    #[test]
+    fn eviction_listener() {
+        use std::sync::Mutex;
+
+        let clock = Arc::new(ManualClock::new());
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let log = evicted.clone();
+        let mut q = S3Fifo::<u32, u32>::builder(4)
+            .small_size(2)
+            .clock(clock.clone())
+            .eviction_listener(move |k, v, reason| log.lock().unwrap().push((*k, *v, reason)))
+            .build()
+            .unwrap();
+        let take = || std::mem::take(&mut *evicted.lock().unwrap());
+
+        q.insert(1, 1);
+        q.insert(2, 2);
+        q.insert(3, 3);
+        assert_eq!(take(), [(1, 1, EvictionReason::Small)]);
+
+        // 1 comes back from the ghost into main, then runs out of room there.
+        q.insert(1, 10);
+        q.insert(4, 4);
+        q.read(&3);
+        q.read(&3);
+        q.insert(5, 5);
+        q.insert(6, 6);
+        q.read(&5);
+        q.read(&5);
+        q.insert(7, 7);
+        assert_eq!(
+            take(),
+            [(2, 2, EvictionReason::Small), (4, 4, EvictionReason::Small), (1, 10, EvictionReason::Main)]
+        );
+
+        assert_eq!(q.insert_with_size(7, 70, 3), Some(7));
+        assert_eq!(q.peek(&7), None);
+        q.insert(5, 50);
+        q.remove(&6);
+        q.insert_with_ttl(8, 8, Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        q.purge_expired();
+        q.retain(|k, _| *k != 3);
+        assert_eq!(
+            take(),
+            [
+                (7, 7, EvictionReason::TooLarge),
+                (5, 5, EvictionReason::Replaced),
+                (6, 6, EvictionReason::Removed),
+                (8, 8, EvictionReason::Expired),
+                (3, 3, EvictionReason::Removed),
+            ]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut q = S3Fifo::<u32, u32>::builder(4).small_size(2).build().unwrap();
+        q.insert(1, 1);
+        q.insert(2, 2);
+        q.read(&2);
+        q.read(&2);
+        q.read(&9);
+        q.insert(3, 3); // 1 demoted to the ghost
+        q.insert(4, 4); // 2 promoted
+        q.insert(1, 1); // ghost hit
+        q.read(&1);
+        q.insert(5, 5); // 3 demoted
+        q.insert(6, 6); // 4 demoted
+        q.read(&6);
+        q.read(&6);
+        q.insert(7, 7); // 5 demoted
+        q.insert(8, 8); // 6 promoted: 2, 1 and 2 again are reinserted, then 1 is evicted
+        q.insert(8, 80); // an update, not an insert
+        assert_eq!(
+            q.stats(),
+            Stats {
+                hits: 5,
+                misses: 1,
+                inserts: 9,
+                promotions: 2,
+                demotions: 4,
+                ghost_hits: 1,
+                reinsertions: 3,
+                evictions: 5,
+                tail_freqs: [5, 2, 3, 0, 0, 0, 0, 0],
+            }
+        );
+        assert_eq!(q.stats().hit_ratio(), 5.0 / 6.0);
+        assert_eq!(q.stats().ghost_hit_ratio(), 1.0 / 9.0);
+        let stats = q.stats();
+        q.reset_stats();
+        assert_eq!(q.stats(), Stats::default());
+
+        // Fingerprints make no difference short of a hash collision.
+        for storage in [GhostStorage::Fingerprints32, GhostStorage::Fingerprints64] {
+            let mut q = S3Fifo::<u32, u32>::builder(4)
+                .small_size(2)
+                .ghost_storage(storage)
+                .build()
+                .unwrap();
+            for (insert, k) in [
+                (true, 1), (true, 2), (false, 2), (false, 2), (false, 9), (true, 3), (true, 4),
+                (true, 1), (false, 1), (true, 5), (true, 6), (false, 6), (false, 6), (true, 7),
+                (true, 8), (true, 8),
+            ] {
+                if insert {
+                    q.insert(k, k);
+                } else {
+                    q.read(&k);
+                }
+            }
+            assert_eq!(q.stats(), stats);
+        }
+    }
+
+    #[test]
+    fn event_sink() {
+        use std::sync::{mpsc, Mutex};
+
+        let (tx, rx) = mpsc::channel();
+        let mut q = S3Fifo::<u32, u32>::builder(4).small_size(2).event_sink(tx).build().unwrap();
+        // The same trace as in `stats`.
+        q.insert(1, 1);
+        q.insert(2, 2);
+        q.read(&2);
+        q.read(&2);
+        q.insert(3, 3);
+        let (event, queues) = rx.try_recv().unwrap();
+        assert_eq!(event, CacheEvent::Demoted { weight: 1 });
+        assert_eq!((queues.small_weight, queues.ghost_weight), (1, 1));
+        q.insert(4, 4);
+        q.insert(1, 1);
+        q.read(&1);
+        q.insert(5, 5);
+        q.insert(6, 6);
+        q.read(&6);
+        q.read(&6);
+        q.insert(7, 7);
+        q.insert(8, 8);
+        assert_eq!(
+            rx.try_iter().map(|(event, _)| event).collect::<Vec<_>>(),
+            [
+                CacheEvent::Promoted { weight: 1 },
+                CacheEvent::GhostHit { weight: 1, main_ghost: false },
+                CacheEvent::Demoted { weight: 1 },
+                CacheEvent::Demoted { weight: 1 },
+                CacheEvent::Demoted { weight: 1 },
+                CacheEvent::Reinserted { weight: 1 },
+                CacheEvent::Reinserted { weight: 1 },
+                CacheEvent::Reinserted { weight: 1 },
+                CacheEvent::Evicted { weight: 1 },
+                CacheEvent::Promoted { weight: 1 },
+            ]
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let mut q = S3Fifo::<u32, u32>::builder(4)
+            .small_size(2)
+            .adaptive_small(1, 3)
+            .main_ghost_capacity(GhostCapacity::Entries(4))
+            .small_size_policy(GhostFeedbackPolicy::default())
+            .event_sink(move |event, queues: &QueueState| log.lock().unwrap().push((event, *queues)))
+            .build()
+            .unwrap();
+        for k in [1, 2, 3, 1] {
+            q.insert(k, k);
+        }
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events.iter().map(|(event, _)| *event).collect::<Vec<_>>(),
+            [
+                CacheEvent::Demoted { weight: 1 },
+                CacheEvent::GhostHit { weight: 1, main_ghost: false },
+                CacheEvent::Resized { small_from: 2, main_from: 2 },
+            ]
+        );
+        let queues = events[2].1;
+        assert_eq!((queues.small_size, queues.main_size), (3, 1));
+    }
+
+    #[test]
+    fn remove() {
+        let mut q = S3Fifo::<u32, u32>::builder(6).small_size(2).build().unwrap();
+        for k in 0..6 {
+            q.insert(k, k);
+            q.read(&k);
+            q.read(&k);
+        }
+        assert_eq!(q.remove(&5), Some(5));
+        assert_eq!(q.remove(&5), None);
+        assert!(q.read(&5).is_none());
+        assert_eq!(q.len(), 5);
+
+        // Reinserting a removed key must not be confused with its stale queue slot.
+        q.insert(5, 50);
+        for k in 0..1_000 {
+            q.insert(k + 100, k);
+            q.remove(&(k + 100));
+        }
+        assert_eq!(q.read(&5), Some(&50));
+        assert!(q.small.len() + q.main.len() <= 2 * q.len() + 16);
+        assert!(q.small_weight <= q.small_size && q.main_weight <= q.main_size);
+    }
+
+    #[test]
+    fn bulk_invalidation() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut q = S3Fifo::<u32, u32>::builder(50).small_size(10).build().unwrap();
+        for i in 0 .. 10_000 {
+            let k = rng.gen_range(0..100);
+            match rng.gen_range(0..8) {
+                0..=2 => {
+                    q.read(&k);
+                }
+                3..=5 => {
+                    q.insert(k, k);
+                }
+                6 => {
+                    q.remove_if(&k, |v| v % 2 == 0);
+                }
+                _ => {
+                    q.forget(&k);
+                    assert!(!q.ghost.contains(&k));
+                }
+            }
+            if i % 1_000 == 0 {
+                q.retain(|k, _| k % 3 != 0);
+                assert!(q.index.keys().all(|k| **k % 3 != 0));
+            }
+            let index = &q.index;
+            let live = |keys: &VecDeque<Arc<u32>>| keys.iter().filter(|k| resident(index, k).is_some()).count();
+            assert_eq!(live(&q.small) + live(&q.main), q.len());
+            assert!(q.small_weight <= q.small_size);
+            assert!(q.main_weight <= q.main_size);
+            assert!(q.ghost.weight() <= q.main_size);
+            q.ghost.assert_consistent();
+        }
+    }
+
+    #[test]
+    fn ghost_feedback_sizing() {
+        let mut q = S3Fifo::<u32, u32>::builder(4)
+            .small_size(2)
+            .adaptive_small(1, 3)
+            .main_ghost_capacity(GhostCapacity::Entries(4))
+            .small_size_policy(GhostFeedbackPolicy::default())
+            .build()
+            .unwrap();
+        for k in 1..=2 {
+            q.insert(k, k);
+            q.read(&k);
+            q.read(&k);
+        }
+        for k in 3..=6 {
+            q.insert(k, k); // 1 and 2 promoted, then 3 and 4 demoted
+        }
+
+        // A hit in the ghost of small grows small at main's expense, and main evicts into its
+        // own ghost to fit.
+        q.insert(3, 3);
+        assert_eq!((q.small_size, q.main_size), (3, 1));
+        assert!(q.main_ghost.contains(&1) && q.main_ghost.contains(&2));
+
+        // A hit in the ghost of main gives the capacity back.
+        q.insert(1, 1);
+        assert_eq!((q.small_size, q.main_size), (2, 2));
+        assert_eq!(q.index[&1].queue, Queue::Main);
+        assert!(!q.main_ghost.contains(&1));
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut q = S3Fifo::<u32, u32>::builder(100)
+            .adaptive_small(1, 90)
+            .main_ghost_capacity(GhostCapacity::Entries(90))
+            .small_size_policy(GhostFeedbackPolicy::default())
+            .build()
+            .unwrap();
+        let mut sizes = std::collections::HashSet::new();
+        for _ in 0..10_000 {
+            let k = rng.gen_range(0..300);
+            if q.read(&k).is_none() {
+                q.insert(k, k);
+            }
+            assert_eq!(q.small_size + q.main_size, 100);
+            assert!(q.small_weight <= q.small_size && q.main_weight <= q.main_size);
+            q.main_ghost.assert_consistent();
+            sizes.insert(q.small_size);
+        }
+        assert!(sizes.len() > 1);
+    }
+
+    #[test]
+    fn promotion_fits_shrunk_main() {
+        let mut q = S3Fifo::<u32, u32>::builder(100)
+            .small_size(40)
+            .adaptive_small(10, 90)
+            .main_ghost_capacity(GhostCapacity::Entries(4))
+            .small_size_policy(GhostFeedbackPolicy::new(50))
+            .build()
+            .unwrap();
+        q.insert_with_size(1, 1, 10);
+        q.insert_with_size(2, 2, 30);
+        q.read(&2);
+        q.read(&2);
+        q.insert_with_size(3, 3, 10); // 1 demoted
+        q.insert_with_size(1, 1, 10);
+        assert_eq!((q.small_size, q.main_size), (90, 10));
+
+        // 2 was read often enough to be promoted, but no longer fits in main.
+        for k in 10..16 {
+            q.insert_with_size(k, k, 10);
+        }
+        assert!(!q.index.contains_key(&2));
+        assert!(q.ghost.contains(&2));
+        assert!(q.main_weight <= q.main_size);
+    }
+
+    #[test]
+    fn weighted_budgets() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut q = S3Fifo::<u32, u32>::builder(1_000)
+            .small_size(100)
+            .weigher(|_, v| *v as usize)
+            .build()
+            .unwrap();
+
+        for _ in 0 .. 10_000 {
+            let k = rng.gen_range(1..200);
+            if rng.gen_bool(0.5) {
+                if let Some(v) = q.read(&k) {
+                    assert_eq!(v, &k);
+                }
+            } else {
+                q.insert(k, k);
+            }
+            let weigh = |keys: &VecDeque<Arc<u32>>| keys.iter().map(|k| q.index[k].weight).sum::<usize>();
+            assert_eq!(weigh(&q.small), q.small_weight);
+            assert_eq!(weigh(&q.main), q.main_weight);
+            assert!(q.small_weight <= q.small_size);
+            assert!(q.main_weight <= q.main_size);
+            assert!(q.ghost.weight() <= q.main_size);
+        }
+
+        // Too large for small, so it goes straight to main.
+        q.insert_with_size(1_000, 0, 500);
+        assert_eq!(q.index[&1_000].queue, Queue::Main);
+        // Too large for the whole cache.
+        q.insert_with_size(1_001, 0, 1_000);
+        assert!(q.read(&1_001).is_none());
+
+        // Updates that outgrow their queue are treated like new entries of the new size, rather
+        // than evicting others to make room.
+        let mut q = S3Fifo::<u32, u32>::builder(100).small_size(10).build().unwrap();
+        for k in 1..=5 {
+            q.insert(k, k);
+        }
+        assert_eq!(q.insert_with_size(1, 1, 50), Some(1));
+        assert_eq!(q.index[&1].queue, Queue::Main);
+        assert_eq!((q.len(), q.small_weight, q.main_weight), (5, 4, 50));
+        assert_eq!(q.insert_with_size(2, 2, 101), Some(2));
+        assert_eq!(q.len(), 4);
+        assert!(q.peek(&2).is_none());
+        assert_eq!(queues(&q)[..2], [vec![5, 4, 3], vec![1]]);
+
+        // A ghost sized in entries ignores the weights.
+        let mut q = S3Fifo::<u32, u32>::builder(100)
+            .small_size(50)
+            .weigher(|_, v| *v as usize)
+            .ghost_capacity(GhostCapacity::Entries(2))
+            .build()
+            .unwrap();
+        for k in [10, 20, 30, 40] {
+            q.insert(k, k); // 30 demotes 10, and 40 demotes 20 and 30
+        }
+        assert_eq!(q.ghost.weight(), 2);
+        assert!(!q.ghost.contains(&10) && q.ghost.contains(&20) && q.ghost.contains(&30));
+    }
 }
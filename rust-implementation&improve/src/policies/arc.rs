@@ -0,0 +1,205 @@
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::queues::{Queued, Queues};
+use super::CachePolicy;
+use crate::Stats;
+
+/// Which of ARC's four lists a key is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Place {
+    T1 = 0,
+    T2 = 1,
+    B1 = 2,
+    B2 = 3,
+}
+
+impl From<Place> for usize {
+    fn from(place: Place) -> usize {
+        place as usize
+    }
+}
+
+// `None` while the key is only remembered in B1 or B2.
+type ArcEntry<V> = Queued<Place, Option<V>>;
+
+/// ARC, from "ARC: A Self-Tuning, Low Overhead Replacement Cache" by Nimrod Megiddo and
+/// Dharmendra S. Modha. Entries seen once live in T1 and entries seen again in T2, both LRU, and
+/// the keys each of them evicts are remembered in B1 and B2. A hit in B1 raises the target size
+/// of T1 and a hit in B2 lowers it, in proportion to how much smaller the hit ghost is.
+pub struct ArcCache<K, V> {
+    // T1, T2, B1 and B2, each with its most recent key at the front.
+    queues: Queues<K, Place, Option<V>, 4>,
+    // Target size of T1, called p in the paper.
+    target: usize,
+    resident: usize,
+    capacity: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq, V> ArcCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queues: Queues::new(),
+            target: 0,
+            resident: 0,
+            capacity,
+            stats: Stats::default(),
+        }
+    }
+
+    fn size(&self, place: Place) -> usize {
+        self.queues.size(place)
+    }
+
+    fn push(&mut self, key: Arc<K>, value: V, size: usize, place: Place) {
+        self.queues.push(key, Some(value), size, place);
+        self.resident += 1;
+    }
+
+    /// Takes `key` off whichever list it is on, and forgets it.
+    fn detach(&mut self, key: &K) -> Option<ArcEntry<V>> {
+        let entry = self.queues.detach(key)?;
+        if entry.value.is_some() {
+            self.resident -= 1;
+        }
+        Some(entry)
+    }
+
+    /// The paper's REPLACE: evicts from T1 into B1 while T1 is above its target, and from T2
+    /// into B2 otherwise, until `size` more fits. After a hit in B2, T1 also gives way when it is
+    /// exactly on target.
+    fn replace(&mut self, size: usize, b2_hit: bool) {
+        while self.size(Place::T1) + self.size(Place::T2) + size > self.capacity {
+            let t1 = self.size(Place::T1);
+            let t1_first = t1 > self.target
+                || (b2_hit && t1 == self.target)
+                || self.queues.is_empty(Place::T2);
+            let (from, to) = match (self.queues.oldest(Place::T1), self.queues.oldest(Place::T2)) {
+                (Some(key), _) if t1_first => (key, Place::B1),
+                (_, Some(key)) => (key, Place::B2),
+                (Some(key), None) => (key, Place::B1),
+                (None, None) => break,
+            };
+            self.queues.index.get_mut(&from).unwrap().value = None;
+            self.resident -= 1;
+            self.stats.evictions += 1;
+            self.queues.relocate(&from, to);
+        }
+    }
+
+    /// Keeps T1 and B1 together within the capacity, and all four lists within twice that.
+    fn trim_ghosts(&mut self) {
+        while self.size(Place::T1) + self.size(Place::B1) > self.capacity {
+            let Some(key) = self.queues.oldest(Place::B1) else {
+                break;
+            };
+            self.detach(&key);
+        }
+        while self.queues.total() > 2 * self.capacity {
+            let Some(key) = self.queues.oldest(Place::B2) else {
+                break;
+            };
+            self.detach(&key);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for ArcCache<K, V> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.capacity {
+            return self.remove(&key);
+        }
+        let (b1, b2) = (self.size(Place::B1).max(1), self.size(Place::B2).max(1));
+        match self.queues.index.get(&key) {
+            Some(entry) if entry.value.is_some() => {
+                let previous = self.queues.resize(&key, size).unwrap().value.replace(value);
+                self.replace(0, false);
+                self.trim_ghosts();
+                return previous;
+            }
+            Some(entry) => {
+                let b2_hit = entry.place == Place::B2;
+                if b2_hit {
+                    self.target = self.target.saturating_sub(size * (b1 / b2).max(1));
+                } else {
+                    self.target = (self.target + size * (b2 / b1).max(1)).min(self.capacity);
+                }
+                self.detach(&key);
+                self.stats.ghost_hits += 1;
+                self.replace(size, b2_hit);
+                self.push(Arc::new(key), value, size, Place::T2);
+            }
+            None => {
+                self.replace(size, false);
+                self.push(Arc::new(key), value, size, Place::T1);
+            }
+        }
+        self.stats.inserts += 1;
+        self.trim_ghosts();
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.peek(key).is_none() {
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        self.queues.relocate(key, Place::T2);
+        self.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.queues.index.get(key)?.value.as_ref()
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.peek(key)?;
+        self.detach(key)?.value
+    }
+
+    fn len(&self) -> usize {
+        self.resident
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapts_to_ghost_hits() {
+        let mut cache = ArcCache::new(4);
+        for k in 1..=4 {
+            cache.insert(k, k);
+        }
+        cache.get(&1);
+        cache.get(&2);
+        cache.insert(5, 5); // 3 evicted from T1 into B1
+
+        // A hit in B1 makes room for more in T1.
+        cache.insert(3, 3);
+        assert_eq!(cache.target, 1);
+        assert_eq!(cache.queues.index[&3].place, Place::T2);
+        assert_eq!(cache.queues.index[&4].place, Place::B1);
+
+        // T1 is on target, so 1 is evicted from T2 into B2, and its hit there lowers the target
+        // again.
+        cache.insert(6, 6);
+        assert_eq!(cache.queues.index[&1].place, Place::B2);
+        cache.insert(1, 1);
+        assert_eq!(cache.target, 0);
+        assert_eq!(cache.queues.index[&5].place, Place::B1);
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.stats().ghost_hits, 2);
+    }
+}
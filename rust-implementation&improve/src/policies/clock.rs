@@ -0,0 +1,50 @@
+use std::hash::Hash;
+
+use super::list::Handle;
+use super::list_cache::{Eviction, ListCache, ListCore};
+
+/// CLOCK with one reference bit, kept as a FIFO queue: an entry at the tail that was read since
+/// it was queued has its bit cleared and goes back to the head instead of being evicted.
+pub type ClockCache<K, V> = ListCache<K, V, Clock>;
+
+/// The eviction choice of [`ClockCache`], whose hand is always at the back of the queue.
+#[derive(Default)]
+pub struct Clock;
+
+impl<K: Hash + Eq, V> Eviction<K, V> for Clock {
+    fn victim(&mut self, core: &mut ListCore<K, V>) -> Handle {
+        loop {
+            let tail = core.list.back().unwrap();
+            let entry = core.entry_mut(tail);
+            if !entry.accessed {
+                return tail;
+            }
+            entry.accessed = false;
+            core.list.move_to_front(tail);
+            core.stats.reinsertions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CachePolicy;
+
+    #[test]
+    fn second_chance() {
+        let mut cache = ClockCache::new(3);
+        for k in 0..3 {
+            cache.insert(k, k);
+        }
+        cache.get(&0);
+        cache.get(&1);
+        // 0 and 1 go around once more, and 2 is evicted.
+        cache.insert(3, 3);
+        assert_eq!(cache.peek(&2), None);
+        // Their bits are now clear, so 0 is next.
+        cache.insert(4, 4);
+        assert_eq!(cache.peek(&0), None);
+        assert_eq!(cache.stats().reinsertions, 2);
+    }
+}
@@ -0,0 +1,36 @@
+use super::list::Handle;
+use super::list_cache::{Eviction, ListCache, ListCore};
+
+/// Evicts in insertion order, ignoring accesses altogether.
+pub type FifoCache<K, V> = ListCache<K, V, Fifo>;
+
+/// The eviction choice of [`FifoCache`].
+#[derive(Default)]
+pub struct Fifo;
+
+impl<K, V> Eviction<K, V> for Fifo {
+    fn victim(&mut self, core: &mut ListCore<K, V>) -> Handle {
+        core.list.back().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CachePolicy;
+
+    #[test]
+    fn evicts_in_insertion_order() {
+        let mut cache = FifoCache::new(3);
+        for k in 0..3 {
+            cache.insert(k, k);
+        }
+        cache.get(&0);
+        cache.insert(3, 3);
+        assert_eq!(cache.peek(&0), None);
+        cache.insert_with_size(4, 4, 2);
+        assert!(cache.peek(&1).is_none() && cache.peek(&2).is_none());
+        assert!(cache.peek(&3).is_some() && cache.peek(&4).is_some());
+        assert_eq!(cache.stats().evictions, 3);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::list::{Handle, List};
+use super::CachePolicy;
+use crate::Stats;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Lir,
+    Hir,
+    NonResident,
+}
+
+struct LirsEntry<V> {
+    // `None` while the key is non-resident.
+    value: Option<V>,
+    size: usize,
+    status: Status,
+    // Where the key is on the stack S, if it is.
+    stack: Option<Handle>,
+    // Where the key is on Q while a resident HIR, or on the non-resident list otherwise.
+    queue: Option<Handle>,
+}
+
+/// LIRS, from "LIRS: An Efficient Low Inter-reference Recency Set Replacement Policy to Improve
+/// Buffer Cache Performance" by Song Jiang and Xiaodong Zhang. Entries with a short reuse
+/// distance are LIR and are never evicted directly; the rest are HIR, and only the resident HIR
+/// entries in the small queue Q are eviction candidates. The recency stack S keeps the keys
+/// recent enough to be promoted, including non-resident ones, and is pruned so that a LIR entry
+/// is always at its bottom.
+pub struct LirsCache<K, V> {
+    index: HashMap<Arc<K>, LirsEntry<V>>,
+    // Most recent at the front of each.
+    stack: List<Arc<K>>,
+    queue: List<Arc<K>>,
+    // Non-resident keys, which are forgotten oldest first once they outweigh the capacity.
+    ghosts: List<Arc<K>>,
+    lir_size: usize,
+    hir_size: usize,
+    ghost_size: usize,
+    lir_capacity: usize,
+    resident: usize,
+    capacity: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq, V> LirsCache<K, V> {
+    /// Reserves 1% of the capacity for resident HIR entries, as the paper does.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_hir_capacity(capacity, (capacity / 100).max(1))
+    }
+
+    /// A cache of `capacity` of which `hir_capacity` is reserved for resident HIR entries.
+    pub fn with_hir_capacity(capacity: usize, hir_capacity: usize) -> Self {
+        Self {
+            index: HashMap::new(),
+            stack: List::new(),
+            queue: List::new(),
+            ghosts: List::new(),
+            lir_size: 0,
+            hir_size: 0,
+            ghost_size: 0,
+            lir_capacity: capacity.saturating_sub(hir_capacity),
+            resident: 0,
+            capacity,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Pops HIR and non-resident keys off the bottom of the stack, forgetting the non-resident
+    /// ones, until a LIR entry is at the bottom.
+    fn prune(&mut self) {
+        while let Some(bottom) = self.stack.back() {
+            let entry = self.index.get_mut(self.stack.get(bottom)).unwrap();
+            if entry.status == Status::Lir {
+                break;
+            }
+            entry.stack = None;
+            let status = entry.status;
+            let key = self.stack.remove(bottom);
+            if status == Status::NonResident {
+                self.forget(&key);
+            }
+        }
+    }
+
+    /// Drops all trace of a non-resident key.
+    fn forget(&mut self, key: &K) {
+        let Some(entry) = self.index.remove(key) else {
+            return;
+        };
+        if let Some(at) = entry.stack {
+            self.stack.remove(at);
+        }
+        if let Some(at) = entry.queue {
+            self.ghosts.remove(at);
+        }
+        self.ghost_size -= entry.size;
+    }
+
+    /// Turns the LIR entry at the bottom of the stack into a resident HIR entry at the front of
+    /// Q. Returns false when there are no LIR entries left.
+    fn demote(&mut self) -> bool {
+        self.prune();
+        let Some(bottom) = self.stack.back() else {
+            return false;
+        };
+        let key = self.stack.remove(bottom);
+        let entry = self.index.get_mut(&key).unwrap();
+        entry.stack = None;
+        entry.status = Status::Hir;
+        entry.queue = Some(self.queue.push_front(key));
+        self.lir_size -= entry.size;
+        self.hir_size += entry.size;
+        self.stats.demotions += 1;
+        self.prune();
+        true
+    }
+
+    /// Evicts resident HIR entries from the back of Q until `size` more fits. Those still on the
+    /// stack are remembered as non-resident.
+    fn make_room(&mut self, size: usize) {
+        while self.lir_size + self.hir_size + size > self.capacity {
+            let Some(key) = self.queue.pop_back() else {
+                if self.demote() {
+                    continue;
+                }
+                break;
+            };
+            let entry = self.index.get_mut(&key).unwrap();
+            self.hir_size -= entry.size;
+            self.resident -= 1;
+            self.stats.evictions += 1;
+            if entry.stack.is_none() {
+                self.index.remove(&key);
+                continue;
+            }
+            entry.value = None;
+            entry.status = Status::NonResident;
+            entry.queue = Some(self.ghosts.push_front(key));
+            self.ghost_size += entry.size;
+            while self.ghost_size > self.capacity {
+                let Some(oldest) = self.ghosts.back() else {
+                    break;
+                };
+                let key = self.ghosts.get(oldest).clone();
+                self.forget(&key);
+            }
+        }
+    }
+
+    fn admit(&mut self, key: K, value: V, size: usize, status: Status) {
+        let key = Arc::new(key);
+        let stack = Some(self.stack.push_front(key.clone()));
+        let queue = (status == Status::Hir).then(|| self.queue.push_front(key.clone()));
+        match status {
+            Status::Lir => self.lir_size += size,
+            _ => self.hir_size += size,
+        }
+        self.resident += 1;
+        let value = Some(value);
+        self.index.insert(key, LirsEntry { value, size, status, stack, queue });
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for LirsCache<K, V> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.capacity {
+            return self.remove(&key);
+        }
+        if let Some(entry) = self.index.get_mut(&key).filter(|entry| entry.value.is_some()) {
+            match entry.status {
+                Status::Lir => self.lir_size = self.lir_size - entry.size + size,
+                _ => self.hir_size = self.hir_size - entry.size + size,
+            }
+            entry.size = size;
+            let previous = entry.value.replace(value);
+            self.make_room(0);
+            return previous;
+        }
+        // A non-resident key still on the stack was reused within the LIR entries' recency, so
+        // it comes back as LIR; it is readmitted at the top of the stack either way.
+        let ghost_hit = self.index.contains_key(&key);
+        if ghost_hit {
+            self.forget(&key);
+            self.stats.ghost_hits += 1;
+        }
+        self.make_room(size);
+        self.stats.inserts += 1;
+        if ghost_hit || self.lir_size + size <= self.lir_capacity {
+            if ghost_hit {
+                self.stats.promotions += 1;
+            }
+            self.admit(key, value, size, Status::Lir);
+            while self.lir_size > self.lir_capacity && self.demote() {}
+        } else {
+            self.admit(key, value, size, Status::Hir);
+        }
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let Some(entry) = self.index.get_mut(key).filter(|entry| entry.value.is_some()) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        let (status, size, stack, queue) = (entry.status, entry.size, entry.stack, entry.queue);
+        match (status, stack) {
+            (Status::Lir, _) => {
+                self.stack.move_to_front(stack.unwrap());
+                self.prune();
+            }
+            // Still on the stack, so its reuse distance beats the bottom LIR entry's.
+            (_, Some(at)) => {
+                self.stack.move_to_front(at);
+                self.queue.remove(queue.unwrap());
+                entry.status = Status::Lir;
+                entry.queue = None;
+                self.hir_size -= size;
+                self.lir_size += size;
+                self.stats.promotions += 1;
+                while self.lir_size > self.lir_capacity && self.demote() {}
+            }
+            (_, None) => {
+                let key = self.queue.remove(queue.unwrap());
+                entry.stack = Some(self.stack.push_front(key.clone()));
+                entry.queue = Some(self.queue.push_front(key));
+            }
+        }
+        self.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.index.get(key)?.value.as_ref()
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.peek(key)?;
+        let entry = self.index.remove(key)?;
+        if let Some(at) = entry.stack {
+            self.stack.remove(at);
+        }
+        if let Some(at) = entry.queue {
+            self.queue.remove(at);
+        }
+        match entry.status {
+            Status::Lir => self.lir_size -= entry.size,
+            _ => self.hir_size -= entry.size,
+        }
+        self.resident -= 1;
+        self.prune();
+        entry.value
+    }
+
+    fn len(&self) -> usize {
+        self.resident
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promotes_by_reuse_distance() {
+        let mut cache = LirsCache::with_hir_capacity(3, 1);
+        cache.insert(1, 1);
+        cache.insert(2, 2);
+        cache.insert(3, 3);
+        assert_eq!(cache.index[&3].status, Status::Hir);
+        // 3 is evicted from Q but stays on the stack as non-resident.
+        cache.insert(4, 4);
+        assert_eq!(cache.index[&3].status, Status::NonResident);
+
+        // Reused while on the stack, 3 comes back as LIR, and the bottom LIR entry 2 makes way.
+        // Pruning then drops 4, which was evicted to make room.
+        cache.get(&1);
+        cache.insert(3, 3);
+        assert_eq!(cache.index[&3].status, Status::Lir);
+        assert_eq!(cache.index[&2].status, Status::Hir);
+        assert!(!cache.index.contains_key(&4));
+
+        // A resident HIR entry off the stack needs two reads: one to rejoin the stack, and one
+        // to be promoted from it.
+        cache.get(&2);
+        assert_eq!(cache.index[&2].status, Status::Hir);
+        cache.get(&2);
+        assert_eq!(cache.index[&2].status, Status::Lir);
+        assert_eq!(cache.index[&1].status, Status::Hir);
+
+        let stats = cache.stats();
+        assert_eq!((stats.ghost_hits, stats.promotions, stats.demotions), (1, 2, 2));
+        assert_eq!(cache.len(), 3);
+    }
+}
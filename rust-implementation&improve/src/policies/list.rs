@@ -0,0 +1,145 @@
+//! A doubly linked list on a slab, for the queues and stacks of the reference policies.
+
+/// Refers to an item in a [`List`] for as long as it stays there.
+pub(crate) type Handle = usize;
+
+const NIL: usize = usize::MAX;
+
+struct Slot<T> {
+    item: Option<T>,
+    prev: usize,
+    next: usize,
+}
+
+/// Items ordered from the front (newest, or most recently used) to the back, with O(1) removal
+/// and reordering by [`Handle`]. Freed slots are reused, so handles stay small integers.
+pub(crate) struct List<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<usize>,
+    front: usize,
+    back: usize,
+    len: usize,
+}
+
+impl<T> List<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            front: NIL,
+            back: NIL,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn back(&self) -> Option<Handle> {
+        (self.back != NIL).then_some(self.back)
+    }
+
+    /// The item in front of `handle`, i.e. the next newer one.
+    pub fn prev(&self, handle: Handle) -> Option<Handle> {
+        let prev = self.slots[handle].prev;
+        (prev != NIL).then_some(prev)
+    }
+
+    pub fn get(&self, handle: Handle) -> &T {
+        self.slots[handle].item.as_ref().expect("stale list handle")
+    }
+
+    pub fn push_front(&mut self, item: T) -> Handle {
+        let slot = Slot {
+            item: Some(item),
+            prev: NIL,
+            next: NIL,
+        };
+        let handle = match self.free.pop() {
+            Some(handle) => {
+                self.slots[handle] = slot;
+                handle
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.link_front(handle);
+        self.len += 1;
+        handle
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> T {
+        self.unlink(handle);
+        self.len -= 1;
+        self.free.push(handle);
+        self.slots[handle].item.take().expect("stale list handle")
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.back().map(|handle| self.remove(handle))
+    }
+
+    pub fn move_to_front(&mut self, handle: Handle) {
+        if self.front != handle {
+            self.unlink(handle);
+            self.link_front(handle);
+        }
+    }
+
+    fn link_front(&mut self, handle: Handle) {
+        self.slots[handle].prev = NIL;
+        self.slots[handle].next = self.front;
+        match self.front {
+            NIL => self.back = handle,
+            front => self.slots[front].prev = handle,
+        }
+        self.front = handle;
+    }
+
+    fn unlink(&mut self, handle: Handle) {
+        let Slot { prev, next, .. } = self.slots[handle];
+        match prev {
+            NIL => self.front = next,
+            prev => self.slots[prev].next = next,
+        }
+        match next {
+            NIL => self.back = prev,
+            next => self.slots[next].prev = prev,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(list: &List<u32>) -> Vec<u32> {
+        let mut items = Vec::new();
+        let mut at = list.back();
+        while let Some(handle) = at {
+            items.push(*list.get(handle));
+            at = list.prev(handle);
+        }
+        items.reverse();
+        items
+    }
+
+    #[test]
+    fn links() {
+        let mut list = List::new();
+        let handles = (0..5).map(|i| list.push_front(i)).collect::<Vec<_>>();
+        assert_eq!(items(&list), [4, 3, 2, 1, 0]);
+        list.move_to_front(handles[0]);
+        assert_eq!(list.remove(handles[2]), 2);
+        assert_eq!(items(&list), [0, 4, 3, 1]);
+        assert_eq!(list.pop_back(), Some(1));
+        // The freed slot is reused.
+        assert!(handles.contains(&list.push_front(5)));
+        assert_eq!(items(&list), [5, 0, 4, 3]);
+        while list.pop_back().is_some() {}
+        assert!(list.is_empty() && list.back().is_none());
+    }
+}
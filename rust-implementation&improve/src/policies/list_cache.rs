@@ -0,0 +1,161 @@
+//! The single-list policies' shared bookkeeping, which each of them tells apart with an
+//! [`Eviction`] strategy.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::list::{Handle, List};
+use super::CachePolicy;
+use crate::Stats;
+
+/// A resident entry of the single-list policies.
+pub(super) struct Entry<V> {
+    value: V,
+    size: usize,
+    handle: Handle,
+    // Whether the entry was read since it was inserted, or since the hand last passed over it.
+    pub(super) accessed: bool,
+}
+
+impl<V> Entry<V> {
+    fn new(value: V, size: usize, handle: Handle) -> Self {
+        Self {
+            value,
+            size,
+            handle,
+            accessed: false,
+        }
+    }
+}
+
+/// The index, list and size accounting of the single-list policies.
+pub(super) struct ListCore<K, V> {
+    index: HashMap<Arc<K>, Entry<V>>,
+    // Newest, or most recently used, at the front.
+    pub(super) list: List<Arc<K>>,
+    used: usize,
+    capacity: usize,
+    pub(super) stats: Stats,
+}
+
+impl<K: Hash + Eq, V> ListCore<K, V> {
+    pub(super) fn entry_mut(&mut self, handle: Handle) -> &mut Entry<V> {
+        self.index.get_mut(self.list.get(handle)).unwrap()
+    }
+
+    /// Evicts the victims `eviction` picks until an entry of `size` fits.
+    fn evict(&mut self, eviction: &mut impl Eviction<K, V>, size: usize) {
+        while self.used + size > self.capacity && !self.list.is_empty() {
+            let victim = eviction.victim(self);
+            eviction.on_remove(self, victim);
+            let key = self.list.remove(victim);
+            if let Some(entry) = self.index.remove(&key) {
+                self.used -= entry.size;
+                self.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+/// What tells the single-list policies apart: how a hit reorders the list, and which entry goes.
+pub(super) trait Eviction<K, V> {
+    /// Picks the entry to evict from a list that is not empty, reordering the entries it passes
+    /// over or clearing their access bits as the policy sees fit.
+    fn victim(&mut self, core: &mut ListCore<K, V>) -> Handle;
+
+    /// Reacts to a hit on the entry at `handle`, whose access bit is already set.
+    fn on_hit(&mut self, core: &mut ListCore<K, V>, handle: Handle) {
+        let _ = (core, handle);
+    }
+
+    /// Forgets about the entry at `handle` before it is evicted or removed.
+    fn on_remove(&mut self, core: &ListCore<K, V>, handle: Handle) {
+        let _ = (core, handle);
+    }
+}
+
+/// A cache that keeps its entries in one list and evicts them with `E`: see
+/// [`FifoCache`](super::FifoCache), [`LruCache`](super::LruCache),
+/// [`ClockCache`](super::ClockCache) and [`SieveCache`](super::SieveCache), the only names it is
+/// used under.
+pub struct ListCache<K, V, E> {
+    core: ListCore<K, V>,
+    eviction: E,
+}
+
+impl<K: Hash + Eq, V, E: Default> ListCache<K, V, E> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            core: ListCore {
+                index: HashMap::new(),
+                list: List::new(),
+                used: 0,
+                capacity,
+                stats: Stats::default(),
+            },
+            eviction: E::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, E: Eviction<K, V>> CachePolicy<K, V> for ListCache<K, V, E> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.core.capacity {
+            return self.remove(&key);
+        }
+        let core = &mut self.core;
+        if let Some(entry) = core.index.get_mut(&key) {
+            core.used = core.used - entry.size + size;
+            entry.size = size;
+            let previous = std::mem::replace(&mut entry.value, value);
+            core.evict(&mut self.eviction, 0);
+            return Some(previous);
+        }
+        core.stats.inserts += 1;
+        core.evict(&mut self.eviction, size);
+        let key = Arc::new(key);
+        let handle = core.list.push_front(key.clone());
+        core.index.insert(key, Entry::new(value, size, handle));
+        core.used += size;
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let core = &mut self.core;
+        let Some(entry) = core.index.get_mut(key) else {
+            core.stats.misses += 1;
+            return None;
+        };
+        entry.accessed = true;
+        let handle = entry.handle;
+        core.stats.hits += 1;
+        self.eviction.on_hit(core, handle);
+        self.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.core.index.get(key).map(|entry| &entry.value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let handle = self.core.index.get(key)?.handle;
+        self.eviction.on_remove(&self.core, handle);
+        self.core.list.remove(handle);
+        let entry = self.core.index.remove(key)?;
+        self.core.used -= entry.size;
+        Some(entry.value)
+    }
+
+    fn len(&self) -> usize {
+        self.core.index.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.core.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.core.stats
+    }
+}
@@ -0,0 +1,41 @@
+use super::list::Handle;
+use super::list_cache::{Eviction, ListCache, ListCore};
+
+/// Evicts the least recently used entry.
+pub type LruCache<K, V> = ListCache<K, V, Lru>;
+
+/// The eviction choice of [`LruCache`].
+#[derive(Default)]
+pub struct Lru;
+
+impl<K, V> Eviction<K, V> for Lru {
+    fn victim(&mut self, core: &mut ListCore<K, V>) -> Handle {
+        core.list.back().unwrap()
+    }
+
+    fn on_hit(&mut self, core: &mut ListCore<K, V>, handle: Handle) {
+        core.list.move_to_front(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CachePolicy;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(3);
+        for k in 0..3 {
+            cache.insert(k, k);
+        }
+        cache.get(&0);
+        cache.insert(3, 3);
+        assert_eq!(cache.peek(&1), None);
+        // Peeking does not count as a use.
+        cache.peek(&2);
+        cache.insert(4, 4);
+        assert_eq!(cache.peek(&2), None);
+        assert!(cache.peek(&0).is_some() && cache.peek(&3).is_some());
+    }
+}
@@ -0,0 +1,228 @@
+//! Eviction algorithms to compare S3-FIFO against, all behind the [`CachePolicy`] trait.
+//!
+//! These are straightforward single-threaded reference implementations, written to be easy to
+//! check against their papers rather than to be fast. Like [`S3Fifo`], they take sizes as entry
+//! counts unless entries are inserted with [`CachePolicy::insert_with_size`], in which case
+//! capacities are budgets in the same unit.
+
+use std::hash::Hash;
+
+use crate::{S3Fifo, Stats};
+
+mod arc;
+mod clock;
+mod fifo;
+mod lirs;
+mod list;
+mod list_cache;
+mod lru;
+mod optimal;
+mod queues;
+mod sieve;
+mod sketch;
+mod tiny_lfu;
+mod two_q;
+
+pub use arc::ArcCache;
+pub use clock::ClockCache;
+pub use fifo::FifoCache;
+pub use lirs::LirsCache;
+pub use lru::LruCache;
+pub use optimal::{Optimal, SizeAwareOptimal};
+pub use sieve::SieveCache;
+pub use tiny_lfu::WTinyLfuCache;
+pub use two_q::TwoQCache;
+
+/// A bounded cache that decides for itself what to evict.
+pub trait CachePolicy<K, V> {
+    /// Inserts `value` under `key` with a size of 1, returning the value it replaced if the key
+    /// was resident.
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.insert_with_size(key, value, 1)
+    }
+
+    /// Inserts `value` under `key`, charging `size` against the capacity. Values larger than the
+    /// whole cache are not cached, and any resident value for the key is removed.
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V>;
+
+    /// Returns the value for `key`, counting a hit or a miss and letting the policy learn from
+    /// the access.
+    fn get(&mut self, key: &K) -> Option<&V>;
+
+    /// Returns the value for `key` without counting it as an access.
+    fn peek(&self, key: &K) -> Option<&V>;
+
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    /// Number of resident entries.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total size the cache holds, in entries or in the unit of the sizes it is given.
+    fn capacity(&self) -> usize;
+
+    /// Hits, misses, admissions and evictions so far, plus whichever of the other counters the
+    /// policy has a use for.
+    fn stats(&self) -> Stats;
+
+    /// Tells an offline policy that the following requests are at virtual time `now`, and that
+    /// their key is next requested at `next`, or never again if `None`. Online policies ignore
+    /// this, and need not be told.
+    fn set_next_access(&mut self, now: u64, next: Option<u64>) {
+        let _ = (now, next);
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for S3Fifo<K, V> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        S3Fifo::insert(self, key, value)
+    }
+
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        S3Fifo::insert_with_size(self, key, value, size)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        self.read(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        S3Fifo::peek(self, key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        S3Fifo::remove(self, key)
+    }
+
+    fn len(&self) -> usize {
+        S3Fifo::len(self)
+    }
+
+    fn capacity(&self) -> usize {
+        S3Fifo::capacity(self)
+    }
+
+    fn stats(&self) -> Stats {
+        S3Fifo::stats(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    type Policy = Box<dyn CachePolicy<u32, u32>>;
+
+    fn all(capacity: usize) -> Vec<(&'static str, Policy)> {
+        vec![
+            ("s3fifo", Box::new(S3Fifo::builder(capacity).build().unwrap())),
+            ("fifo", Box::new(FifoCache::new(capacity))),
+            ("lru", Box::new(LruCache::new(capacity))),
+            ("clock", Box::new(ClockCache::new(capacity))),
+            ("sieve", Box::new(SieveCache::new(capacity))),
+            ("arc", Box::new(ArcCache::new(capacity))),
+            ("2q", Box::new(TwoQCache::new(capacity))),
+            ("lirs", Box::new(LirsCache::new(capacity))),
+            ("w-tinylfu", Box::new(WTinyLfuCache::new(capacity))),
+        ]
+    }
+
+    #[test]
+    fn common_contract() {
+        for (name, mut cache) in all(50) {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut reads = 0;
+            for _ in 0..20_000 {
+                // Skewed towards small keys, so that there is something to learn.
+                let bound = rng.gen_range(1..500);
+                let k = rng.gen_range(0..bound);
+                reads += 1;
+                match cache.get(&k) {
+                    Some(v) => assert_eq!(*v, k, "{name}"),
+                    None => {
+                        assert_eq!(cache.insert(k, k), None, "{name}");
+                        assert_eq!(cache.peek(&k), Some(&k), "{name}");
+                    }
+                }
+                assert!(cache.len() <= cache.capacity(), "{name}");
+                if rng.gen_ratio(1, 100) {
+                    let k = rng.gen_range(0..500);
+                    if cache.peek(&k).is_some() {
+                        assert_eq!(cache.remove(&k), Some(k), "{name}");
+                        assert_eq!(cache.peek(&k), None, "{name}");
+                    }
+                }
+            }
+            let stats = cache.stats();
+            assert_eq!(stats.hits + stats.misses, reads, "{name}");
+            assert!(stats.hit_ratio() > 0.15, "{name}: {}", stats.hit_ratio());
+
+            // Updates replace in place, and oversized values are not cached.
+            cache.insert(1_000, 1);
+            assert_eq!(cache.insert(1_000, 2), Some(1), "{name}");
+            assert_eq!(cache.peek(&1_000), Some(&2), "{name}");
+            cache.insert_with_size(1_001, 0, 51);
+            assert_eq!(cache.peek(&1_001), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn optimal_bounds_the_rest() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let trace = (0..20_000)
+            .map(|_| {
+                let bound = rng.gen_range(1..500);
+                rng.gen_range(0..bound)
+            })
+            .collect::<Vec<u32>>();
+        let mut seen = std::collections::HashMap::new();
+        let mut next = vec![None; trace.len()];
+        for (now, k) in trace.iter().enumerate().rev() {
+            next[now] = seen.insert(*k, now as u64);
+        }
+        let replay = |cache: &mut dyn CachePolicy<u32, u32>| {
+            for (now, k) in trace.iter().enumerate() {
+                cache.set_next_access(now as u64, next[now]);
+                if cache.get(k).is_none() {
+                    cache.insert(*k, *k);
+                }
+            }
+            cache.stats().misses
+        };
+        let optimal = replay(&mut Optimal::new(50));
+        // Without sizes to weigh, sampling only costs the size-aware variant a little.
+        let size_aware = replay(&mut SizeAwareOptimal::new(50));
+        assert!(optimal <= size_aware && size_aware < optimal * 11 / 10);
+        for (name, mut cache) in all(50) {
+            let misses = replay(cache.as_mut());
+            assert!(optimal < misses, "{name}: {misses} against {optimal}");
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        for (name, mut cache) in all(1_000) {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut resident = std::collections::HashMap::new();
+            for _ in 0..5_000 {
+                let k = rng.gen_range(0..200);
+                if cache.get(&k).is_some() {
+                    continue;
+                }
+                let size = rng.gen_range(1..100);
+                cache.insert_with_size(k, k, size);
+                resident.insert(k, size);
+                let used = resident
+                    .iter()
+                    .filter(|(k, _)| cache.peek(k).is_some())
+                    .map(|(_, size)| size)
+                    .sum::<usize>();
+                assert!(used <= 1_000, "{name}: {used}");
+            }
+        }
+    }
+}
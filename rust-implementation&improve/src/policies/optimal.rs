@@ -0,0 +1,417 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::CachePolicy;
+use crate::Stats;
+
+// Next access time of keys that are never requested again.
+const NEVER: u64 = u64::MAX;
+
+// Entries scored per eviction by SizeAwareOptimal once there are more than this many.
+const SAMPLES: usize = 64;
+
+struct OracleEntry<V> {
+    value: V,
+    size: usize,
+    next: u64,
+    // Which heap slot is current, for `Optimal`; where the key is in `keys`, for
+    // `SizeAwareOptimal`.
+    slot: usize,
+}
+
+/// A key queued for eviction by `Optimal`, valid as long as its entry still has the same stamp.
+struct Slot<K> {
+    next: u64,
+    stamp: usize,
+    key: Arc<K>,
+}
+
+impl<K> PartialEq for Slot<K> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.next, self.stamp) == (other.next, other.stamp)
+    }
+}
+
+impl<K> Eq for Slot<K> {}
+
+impl<K> PartialOrd for Slot<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for Slot<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.next, self.stamp).cmp(&(other.next, other.stamp))
+    }
+}
+
+/// Belady's offline optimal policy: evicts the entry whose next request is furthest away, or
+/// that is never requested again. It can only be simulated, from traces that record when each
+/// request's key comes up next, and must be told so through [`CachePolicy::set_next_access`]
+/// before each request. Its miss ratio is the lower bound for the other policies when entries
+/// are all the same size.
+///
+/// A new entry that would be needed no sooner than everything it has to displace, or never, is
+/// not admitted at all, since it would be the first to go.
+pub struct Optimal<K, V> {
+    index: HashMap<Arc<K>, OracleEntry<V>>,
+    // Furthest next access on top. Reads push a new slot instead of updating the old one, which
+    // is skipped as stale when it comes up.
+    heap: BinaryHeap<Slot<K>>,
+    stamp: usize,
+    next: u64,
+    used: usize,
+    capacity: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq, V> Optimal<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            index: HashMap::new(),
+            heap: BinaryHeap::new(),
+            stamp: 0,
+            next: NEVER,
+            used: 0,
+            capacity,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Records the advised next access for `key`, and queues it for eviction by it.
+    fn requeue(&mut self, key: Arc<K>) {
+        self.stamp += 1;
+        let entry = self.index.get_mut(&key).unwrap();
+        entry.next = self.next;
+        entry.slot = self.stamp;
+        let (next, stamp) = (self.next, self.stamp);
+        self.heap.push(Slot { next, stamp, key });
+        if self.heap.len() > 2 * self.index.len() + 1 {
+            self.heap = self
+                .index
+                .iter()
+                .map(|(key, entry)| Slot {
+                    next: entry.next,
+                    stamp: entry.slot,
+                    key: key.clone(),
+                })
+                .collect();
+        }
+    }
+
+    /// The furthest next access of the resident entries, dropping stale slots on the way.
+    fn furthest(&mut self) -> Option<u64> {
+        while let Some(slot) = self.heap.peek() {
+            let current = self.index.get(&slot.key);
+            if current.is_some_and(|entry| entry.slot == slot.stamp) {
+                return Some(slot.next);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    fn evict(&mut self, size: usize) {
+        while self.used + size > self.capacity {
+            let Some(slot) = self.heap.pop() else {
+                break;
+            };
+            let current = self.index.get(&slot.key);
+            let Some(entry) = current.filter(|entry| entry.slot == slot.stamp) else {
+                continue;
+            };
+            self.used -= entry.size;
+            self.index.remove(&slot.key);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for Optimal<K, V> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.capacity {
+            return self.remove(&key);
+        }
+        if let Some((key, entry)) = self.index.get_key_value(&key) {
+            let (key, old_size) = (key.clone(), entry.size);
+            let entry = self.index.get_mut(&key).unwrap();
+            entry.size = size;
+            let previous = std::mem::replace(&mut entry.value, value);
+            self.used = self.used - old_size + size;
+            self.requeue(key);
+            self.evict(0);
+            return Some(previous);
+        }
+        if self.used + size > self.capacity && self.furthest().is_some_and(|n| self.next >= n) {
+            return None;
+        }
+        self.stats.inserts += 1;
+        self.evict(size);
+        let key = Arc::new(key);
+        let (next, slot) = (NEVER, 0);
+        let entry = OracleEntry { value, size, next, slot };
+        self.index.insert(key.clone(), entry);
+        self.used += size;
+        self.requeue(key);
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let Some((key, _)) = self.index.get_key_value(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        let key = key.clone();
+        self.requeue(key.clone());
+        self.peek(&key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.index.get(key).map(|entry| &entry.value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.index.remove(key)?;
+        self.used -= entry.size;
+        Some(entry.value)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn set_next_access(&mut self, _now: u64, next: Option<u64>) {
+        self.next = next.unwrap_or(NEVER);
+    }
+}
+
+/// A size-aware take on [`Optimal`]: evicts the entry with the largest product of size and time
+/// to its next request, so that one large entry is given up before several small ones needed
+/// sooner. Exact size-aware optimal eviction is NP-hard, and this is a heuristic that scores a
+/// fixed-size random sample of entries per eviction. Like `Optimal`, it must be told each
+/// request's next access, and it does not admit a new entry that scores at least as high as the
+/// entry it would evict first.
+pub struct SizeAwareOptimal<K, V> {
+    index: HashMap<Arc<K>, OracleEntry<V>>,
+    // Resident keys in no particular order, to sample from.
+    keys: Vec<Arc<K>>,
+    // State of the xorshift generator that picks the samples.
+    rng: u64,
+    now: u64,
+    next: u64,
+    used: usize,
+    capacity: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq, V> SizeAwareOptimal<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            index: HashMap::new(),
+            keys: Vec::new(),
+            rng: 0x9e37_79b9_7f4a_7c15,
+            now: 0,
+            next: NEVER,
+            used: 0,
+            capacity,
+            stats: Stats::default(),
+        }
+    }
+
+    fn random(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng as usize
+    }
+
+    fn score(&self, key: &K) -> u128 {
+        let entry = &self.index[key];
+        self.weigh(entry.next, entry.size)
+    }
+
+    fn weigh(&self, next: u64, size: usize) -> u128 {
+        match next {
+            NEVER => u128::MAX,
+            next => u128::from(next.saturating_sub(self.now)) * size as u128,
+        }
+    }
+
+    fn detach(&mut self, key: &K) -> Option<OracleEntry<V>> {
+        let entry = self.index.remove(key)?;
+        self.keys.swap_remove(entry.slot);
+        if let Some(moved) = self.keys.get(entry.slot) {
+            self.index.get_mut(moved).unwrap().slot = entry.slot;
+        }
+        self.used -= entry.size;
+        Some(entry)
+    }
+
+    /// The entry with the highest score, of all of them or of a sample. There must be one.
+    fn victim(&mut self) -> Arc<K> {
+        let victim = if self.keys.len() <= SAMPLES {
+            self.keys.iter().max_by_key(|key| self.score(key))
+        } else {
+            let samples: [usize; SAMPLES] =
+                std::array::from_fn(|_| self.random() % self.keys.len());
+            samples
+                .iter()
+                .map(|&at| &self.keys[at])
+                .max_by_key(|key| self.score(key))
+        };
+        victim.unwrap().clone()
+    }
+
+    fn evict(&mut self, size: usize) {
+        while self.used + size > self.capacity && !self.keys.is_empty() {
+            let victim = self.victim();
+            self.detach(&victim);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for SizeAwareOptimal<K, V> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.capacity {
+            return self.remove(&key);
+        }
+        if let Some(entry) = self.index.get_mut(&key) {
+            self.used = self.used - entry.size + size;
+            entry.size = size;
+            entry.next = self.next;
+            let previous = std::mem::replace(&mut entry.value, value);
+            self.evict(0);
+            return Some(previous);
+        }
+        if self.used + size > self.capacity && !self.keys.is_empty() {
+            let victim = self.victim();
+            if self.weigh(self.next, size) >= self.score(&victim) {
+                return None;
+            }
+        }
+        self.stats.inserts += 1;
+        self.evict(size);
+        let key = Arc::new(key);
+        let (next, slot) = (self.next, self.keys.len());
+        self.keys.push(key.clone());
+        self.index.insert(key, OracleEntry { value, size, next, slot });
+        self.used += size;
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let Some(entry) = self.index.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        entry.next = self.next;
+        Some(&entry.value)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.index.get(key).map(|entry| &entry.value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.detach(key).map(|entry| entry.value)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn set_next_access(&mut self, now: u64, next: Option<u64>) {
+        self.now = now;
+        self.next = next.unwrap_or(NEVER);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_furthest_next_access() {
+        // Next accesses of 1, 2, 3, 1, 3, 2.
+        let trace = [
+            (1, Some(3)),
+            (2, Some(5)),
+            (3, Some(4)),
+            (1, None),
+            (3, None),
+            (2, None),
+        ];
+        let mut cache = Optimal::new(2);
+        for (now, (k, next)) in trace.into_iter().enumerate() {
+            cache.set_next_access(now as u64, next);
+            if cache.get(&k).is_none() {
+                cache.insert(k, k);
+            }
+            if now == 2 {
+                // 2 is needed after 1, so it makes way for 3.
+                assert_eq!(cache.peek(&2), None);
+            }
+        }
+        assert_eq!(cache.stats().misses, 4);
+    }
+
+    #[test]
+    fn weighs_size_against_distance() {
+        let mut optimal = Optimal::new(10);
+        let mut size_aware = SizeAwareOptimal::new(10);
+        let caches: [&mut dyn CachePolicy<u32, u32>; 2] = [&mut optimal, &mut size_aware];
+        for cache in caches {
+            cache.set_next_access(0, Some(10));
+            cache.insert_with_size(1, 1, 8);
+            cache.set_next_access(1, Some(20));
+            cache.insert_with_size(2, 2, 2);
+            cache.set_next_access(2, Some(5));
+            cache.insert_with_size(3, 3, 2);
+        }
+        // 2 is needed last, but 1 ties up 8 units for 8 requests against 2's 2 for 18.
+        assert_eq!(optimal.peek(&2), None);
+        assert_eq!(size_aware.peek(&1), None);
+        assert_eq!(size_aware.peek(&2), Some(&2));
+    }
+
+    #[test]
+    fn bypasses_entries_needed_last() {
+        // A is read again at 2, B never: B would be the first to go, so it never gets in.
+        let trace = [("a", Some(2)), ("b", None), ("a", None)];
+        let mut optimal = Optimal::new(1);
+        let mut size_aware = SizeAwareOptimal::new(1);
+        let caches: [&mut dyn CachePolicy<&str, u32>; 2] = [&mut optimal, &mut size_aware];
+        for cache in caches {
+            for (now, (k, next)) in trace.into_iter().enumerate() {
+                cache.set_next_access(now as u64, next);
+                if cache.get(&k).is_none() {
+                    cache.insert(k, 0);
+                }
+            }
+            assert_eq!(cache.stats().misses, 2);
+            assert_eq!(cache.stats().evictions, 0);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! The index and the lists of the policies that move entries between several lists: ARC, 2Q and
+//! W-TinyLFU.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::list::{Handle, List};
+
+/// An entry on one of the lists of a [`Queues`], with the policy's own `value`.
+pub(super) struct Queued<P, T> {
+    pub(super) value: T,
+    pub(super) size: usize,
+    pub(super) place: P,
+    handle: Handle,
+}
+
+/// Entries indexed by key, each on one of `N` lists picked by a place `P`, which converts to the
+/// list's index. Each list has its newest, or most recently used, key at the front.
+pub(super) struct Queues<K, P, T, const N: usize> {
+    pub(super) index: HashMap<Arc<K>, Queued<P, T>>,
+    lists: [List<Arc<K>>; N],
+    sizes: [usize; N],
+}
+
+impl<K: Hash + Eq, P: Copy + Into<usize>, T, const N: usize> Queues<K, P, T, N> {
+    pub(super) fn new() -> Self {
+        Self {
+            index: HashMap::new(),
+            lists: std::array::from_fn(|_| List::new()),
+            sizes: [0; N],
+        }
+    }
+
+    /// Total size of the entries at `place`.
+    pub(super) fn size(&self, place: P) -> usize {
+        self.sizes[place.into()]
+    }
+
+    pub(super) fn total(&self) -> usize {
+        self.sizes.iter().sum()
+    }
+
+    pub(super) fn is_empty(&self, place: P) -> bool {
+        self.lists[place.into()].is_empty()
+    }
+
+    /// The key at the back of the list for `place`.
+    pub(super) fn oldest(&self, place: P) -> Option<Arc<K>> {
+        let list = &self.lists[place.into()];
+        list.back().map(|handle| list.get(handle).clone())
+    }
+
+    /// Adds `key`, which must not be indexed yet, at the front of the list for `place`.
+    pub(super) fn push(&mut self, key: Arc<K>, value: T, size: usize, place: P) {
+        let handle = self.lists[place.into()].push_front(key.clone());
+        self.sizes[place.into()] += size;
+        self.index.insert(
+            key,
+            Queued {
+                value,
+                size,
+                place,
+                handle,
+            },
+        );
+    }
+
+    /// Charges `key`'s entry `size` instead of what it was, and returns it for its value to be
+    /// replaced.
+    pub(super) fn resize(&mut self, key: &K, size: usize) -> Option<&mut Queued<P, T>> {
+        let entry = self.index.get_mut(key)?;
+        let place = entry.place.into();
+        self.sizes[place] = self.sizes[place] - entry.size + size;
+        entry.size = size;
+        Some(entry)
+    }
+
+    /// Moves `key` to the front of the list it is on.
+    pub(super) fn touch(&mut self, key: &K) {
+        let entry = &self.index[key];
+        self.lists[entry.place.into()].move_to_front(entry.handle);
+    }
+
+    /// Moves `key` to the front of the list for `to`.
+    pub(super) fn relocate(&mut self, key: &K, to: P) {
+        let entry = self.index.get_mut(key).unwrap();
+        let key = self.lists[entry.place.into()].remove(entry.handle);
+        self.sizes[entry.place.into()] -= entry.size;
+        entry.handle = self.lists[to.into()].push_front(key);
+        self.sizes[to.into()] += entry.size;
+        entry.place = to;
+    }
+
+    /// Takes `key` off whichever list it is on, and forgets it.
+    pub(super) fn detach(&mut self, key: &K) -> Option<Queued<P, T>> {
+        let entry = self.index.remove(key)?;
+        self.lists[entry.place.into()].remove(entry.handle);
+        self.sizes[entry.place.into()] -= entry.size;
+        Some(entry)
+    }
+}
@@ -0,0 +1,65 @@
+use std::hash::Hash;
+
+use super::list::Handle;
+use super::list_cache::{Eviction, ListCache, ListCore};
+
+/// SIEVE, from "SIEVE is Simpler than LRU" by Yazhuo Zhang, et al. A FIFO queue whose hand
+/// moves from the tail towards the head, skipping and clearing visited entries and evicting the
+/// first unvisited one. Unlike CLOCK, survivors keep their place instead of moving to the head.
+pub type SieveCache<K, V> = ListCache<K, V, Sieve>;
+
+/// The eviction choice of [`SieveCache`].
+#[derive(Default)]
+pub struct Sieve {
+    // Where the last eviction stopped; the back of the queue when `None`.
+    hand: Option<Handle>,
+}
+
+impl<K: Hash + Eq, V> Eviction<K, V> for Sieve {
+    fn victim(&mut self, core: &mut ListCore<K, V>) -> Handle {
+        let mut at = self.hand.or(core.list.back()).unwrap();
+        loop {
+            let entry = core.entry_mut(at);
+            if !entry.accessed {
+                break;
+            }
+            entry.accessed = false;
+            at = core.list.prev(at).or(core.list.back()).unwrap();
+        }
+        // `on_remove` moves the hand on to the next newer entry.
+        self.hand = Some(at);
+        at
+    }
+
+    fn on_remove(&mut self, core: &ListCore<K, V>, handle: Handle) {
+        if self.hand == Some(handle) {
+            self.hand = core.list.prev(handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CachePolicy;
+
+    #[test]
+    fn hand_keeps_its_place() {
+        let mut cache = SieveCache::new(4);
+        for k in 0..4 {
+            cache.insert(k, k);
+        }
+        cache.get(&0);
+        cache.get(&2);
+        // The hand passes 0 and evicts 1, stopping at 2.
+        cache.insert(4, 4);
+        assert_eq!(cache.peek(&1), None);
+        // 2 is passed over in turn, so 3 goes next.
+        cache.insert(5, 5);
+        assert_eq!(cache.peek(&3), None);
+        // The hand carries on towards the head, so 4 goes before the older 0 and 2.
+        cache.insert(6, 6);
+        assert_eq!(cache.peek(&4), None);
+        assert!([0, 2, 5, 6].iter().all(|k| cache.peek(k).is_some()));
+    }
+}
@@ -0,0 +1,124 @@
+//! The count-min sketch behind TinyLFU's admission filter.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+const ROWS: usize = 4;
+// Counters are four bits wide in Caffeine; a byte each is simpler and the cap keeps the
+// behaviour the same.
+const MAX_COUNT: u8 = 15;
+const MIN_WIDTH: usize = 16;
+
+/// Approximate access counts over a sliding sample of recent accesses. Each key maps to one
+/// counter in each of four rows, and its estimate is the smallest of them. Every `sample_size`
+/// increments all counters are halved, so that keys that were popular long ago fade out.
+///
+/// Rows start narrow and widen with the number of entries in the cache, as in Caffeine, since a
+/// byte budget says nothing about how many keys there are to count.
+pub(crate) struct FrequencySketch {
+    table: Vec<u8>,
+    // Width of a row, minus one; rows are a power of two wide.
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+    hasher: RandomState,
+}
+
+impl FrequencySketch {
+    pub fn new() -> Self {
+        Self::with_width(MIN_WIDTH)
+    }
+
+    /// Rows `width` wide, with a sample ten times that.
+    fn with_width(width: usize) -> Self {
+        Self {
+            table: vec![0; ROWS * width],
+            mask: width - 1,
+            additions: 0,
+            sample_size: 10 * width,
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Widens the rows to fit a cache of `entries` entries, forgetting all counts like Caffeine
+    /// does. Never narrows them.
+    pub fn ensure_capacity(&mut self, entries: usize) {
+        let width = entries.next_power_of_two();
+        if width > self.mask + 1 {
+            *self = Self::with_width(width);
+        }
+    }
+
+    fn slots<K: Hash>(&self, key: &K) -> [usize; ROWS] {
+        let hash = self.hasher.hash_one(key);
+        let (low, high) = (hash as u32 as usize, (hash >> 32) as usize | 1);
+        let width = self.mask + 1;
+        std::array::from_fn(|row| {
+            row * width + (low.wrapping_add(row.wrapping_mul(high)) & self.mask)
+        })
+    }
+
+    pub fn increment<K: Hash>(&mut self, key: &K) {
+        for slot in self.slots(key) {
+            let count = &mut self.table[slot];
+            *count = (*count + 1).min(MAX_COUNT);
+        }
+        self.additions += 1;
+        if self.additions == self.sample_size {
+            self.reset();
+        }
+    }
+
+    pub fn frequency<K: Hash>(&self, key: &K) -> u8 {
+        self.slots(key)
+            .into_iter()
+            .map(|slot| self.table[slot])
+            .min()
+            .unwrap()
+    }
+
+    fn reset(&mut self) {
+        for count in &mut self.table {
+            *count /= 2;
+        }
+        self.additions /= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages() {
+        let mut sketch = FrequencySketch::new();
+        for _ in 0..20 {
+            sketch.increment(&"popular");
+        }
+        sketch.increment(&"rare");
+        assert_eq!(sketch.frequency(&"popular"), MAX_COUNT);
+        assert!(sketch.frequency(&"rare") >= 1);
+
+        // The 160th increment halves every counter.
+        for i in 0..139 {
+            sketch.increment(&i);
+        }
+        assert_eq!(sketch.additions, 80);
+        assert_eq!(sketch.frequency(&"popular"), MAX_COUNT / 2);
+    }
+
+    #[test]
+    fn grows_with_entries() {
+        let mut sketch = FrequencySketch::new();
+        sketch.increment(&"key");
+        sketch.ensure_capacity(10);
+        assert_eq!(sketch.mask + 1, MIN_WIDTH);
+        assert_eq!(sketch.frequency(&"key"), 1);
+
+        sketch.ensure_capacity(100);
+        assert_eq!(sketch.mask + 1, 128);
+        assert_eq!(sketch.table.len(), ROWS * 128);
+        assert_eq!(sketch.sample_size, 1280);
+        assert_eq!(sketch.frequency(&"key"), 0);
+    }
+}
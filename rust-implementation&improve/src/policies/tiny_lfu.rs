@@ -0,0 +1,245 @@
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::queues::Queues;
+use super::sketch::FrequencySketch;
+use super::CachePolicy;
+use crate::Stats;
+
+/// Which of W-TinyLFU's three LRU lists an entry is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Place {
+    Window = 0,
+    Probation = 1,
+    Protected = 2,
+}
+
+impl From<Place> for usize {
+    fn from(place: Place) -> usize {
+        place as usize
+    }
+}
+
+/// W-TinyLFU, from "TinyLFU: A Highly Efficient Cache Admission Policy" by Gil Einziger, Roy
+/// Friedman and Ben Manes, as used by Caffeine. New entries go to a small LRU window. Entries
+/// leaving the window are only admitted to the main cache, a segmented LRU, if a count-min
+/// sketch of recent reads says they are more popular than the main cache's next victim.
+///
+/// The sketch counts reads, hits and misses alike, so entries that are inserted without being
+/// read first start with no history.
+pub struct WTinyLfuCache<K, V> {
+    // The window, probation and protected lists, each with its most recently used key at the
+    // front.
+    queues: Queues<K, Place, V, 3>,
+    sketch: FrequencySketch,
+    window_capacity: usize,
+    protected_capacity: usize,
+    capacity: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq, V> WTinyLfuCache<K, V> {
+    /// Gives the window 1% of the capacity, Caffeine's starting point.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_window_capacity(capacity, (capacity / 100).max(1))
+    }
+
+    /// A cache of `capacity` whose window holds `window_capacity`. Of the rest, 80% is kept for
+    /// the protected segment.
+    pub fn with_window_capacity(capacity: usize, window_capacity: usize) -> Self {
+        let window_capacity = window_capacity.min(capacity);
+        Self {
+            queues: Queues::new(),
+            sketch: FrequencySketch::new(),
+            window_capacity,
+            protected_capacity: (capacity - window_capacity) * 4 / 5,
+            capacity,
+            stats: Stats::default(),
+        }
+    }
+
+    fn size(&self, place: Place) -> usize {
+        self.queues.size(place)
+    }
+
+    fn main_size(&self) -> usize {
+        self.size(Place::Probation) + self.size(Place::Protected)
+    }
+
+    fn main_capacity(&self) -> usize {
+        self.capacity - self.window_capacity
+    }
+
+    /// Main's next victim: the least recently used entry on probation, or in protected if
+    /// probation is empty.
+    fn victim(&self) -> Option<Arc<K>> {
+        self.queues
+            .oldest(Place::Probation)
+            .or_else(|| self.queues.oldest(Place::Protected))
+    }
+
+    fn evict_victim(&mut self) -> bool {
+        let Some(victim) = self.victim() else {
+            return false;
+        };
+        self.queues.detach(&victim);
+        self.stats.evictions += 1;
+        true
+    }
+
+    /// Moves entries out of the window while it is over its share, letting each into probation
+    /// only if it has been read more often than the victim it would displace.
+    fn evict(&mut self) {
+        while self.size(Place::Window) > self.window_capacity {
+            let candidate = self.queues.oldest(Place::Window).unwrap();
+            let size = self.queues.index[&candidate].size;
+            if self.main_size() + size > self.main_capacity() {
+                let admit = self.victim().is_some_and(|victim| {
+                    self.sketch.frequency(&candidate) > self.sketch.frequency(&victim)
+                });
+                if !admit {
+                    self.queues.detach(&candidate);
+                    self.stats.evictions += 1;
+                    continue;
+                }
+                // Make room before the candidate joins probation: with probation empty, the
+                // candidate would otherwise be the next victim itself.
+                while self.main_size() + size > self.main_capacity() && self.evict_victim() {}
+            }
+            self.queues.relocate(&candidate, Place::Probation);
+        }
+        while self.main_size() > self.main_capacity() && self.evict_victim() {}
+    }
+
+    /// Demotes protected's least recently used entries to probation while it is over its share.
+    fn trim_protected(&mut self) {
+        while self.size(Place::Protected) > self.protected_capacity {
+            let Some(key) = self.queues.oldest(Place::Protected) else {
+                break;
+            };
+            self.queues.relocate(&key, Place::Probation);
+            self.stats.demotions += 1;
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for WTinyLfuCache<K, V> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.capacity {
+            return self.remove(&key);
+        }
+        if let Some(entry) = self.queues.resize(&key, size) {
+            let previous = std::mem::replace(&mut entry.value, value);
+            self.trim_protected();
+            self.evict();
+            return Some(previous);
+        }
+        self.stats.inserts += 1;
+        self.queues.push(Arc::new(key), value, size, Place::Window);
+        self.sketch.ensure_capacity(self.queues.index.len());
+        self.evict();
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        self.sketch.increment(key);
+        let Some(entry) = self.queues.index.get(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        match entry.place {
+            Place::Probation => {
+                self.queues.relocate(key, Place::Protected);
+                self.stats.promotions += 1;
+                self.trim_protected();
+            }
+            _ => self.queues.touch(key),
+        }
+        self.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.queues.index.get(key).map(|entry| &entry.value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.queues.detach(key).map(|entry| entry.value)
+    }
+
+    fn len(&self) -> usize {
+        self.queues.index.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admits_by_frequency() {
+        let mut cache = WTinyLfuCache::with_window_capacity(4, 1);
+        for k in 1..=4 {
+            cache.insert(k, k);
+        }
+        assert_eq!(cache.main_size(), 3);
+        // Reads on probation move entries to protected, which holds 80% of main.
+        cache.get(&1);
+        cache.get(&2);
+        cache.get(&3);
+        assert_eq!(cache.queues.index[&1].place, Place::Probation);
+        assert_eq!(cache.queues.index[&3].place, Place::Protected);
+        assert_eq!(cache.stats().demotions, 1);
+
+        // 4 has never been read, so it loses against the victim 1, which has.
+        cache.insert(5, 5);
+        assert_eq!(cache.peek(&4), None);
+        assert_eq!(cache.queues.index[&1].place, Place::Probation);
+
+        // 5 has been read more often than 1, so it takes 1's place.
+        cache.get(&5);
+        cache.get(&5);
+        cache.insert(6, 6);
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.queues.index[&5].place, Place::Probation);
+    }
+
+    #[test]
+    fn admitted_candidate_replaces_protected_victim() {
+        let mut cache = WTinyLfuCache::with_window_capacity(11, 1);
+        for k in [1, 2] {
+            cache.insert_with_size(k, k, 4);
+            cache.get(&k);
+        }
+        assert_eq!(cache.size(Place::Protected), 8);
+        assert_eq!(cache.size(Place::Probation), 0);
+
+        // With probation empty, the victim is protected's least recently used entry, 1.
+        for _ in 0..3 {
+            cache.get(&3);
+        }
+        cache.insert_with_size(3, 3, 3);
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.queues.index[&2].place, Place::Protected);
+        assert_eq!(cache.queues.index[&3].place, Place::Probation);
+    }
+
+    #[test]
+    fn sketch_follows_entries_not_bytes() {
+        // A sketch as wide as the byte capacity would need a terabyte.
+        let mut cache = WTinyLfuCache::new(1 << 40);
+        for k in 0..100 {
+            cache.insert_with_size(k, k, 1 << 20);
+            cache.get(&k);
+        }
+        assert_eq!(cache.len(), 100);
+    }
+}
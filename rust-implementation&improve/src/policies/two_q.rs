@@ -0,0 +1,202 @@
+use std::hash::Hash;
+use std::sync::Arc;
+
+use super::queues::{Queued, Queues};
+use super::CachePolicy;
+use crate::Stats;
+
+/// Which of 2Q's three queues a key is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Place {
+    In = 0,
+    Main = 1,
+    Out = 2,
+}
+
+impl From<Place> for usize {
+    fn from(place: Place) -> usize {
+        place as usize
+    }
+}
+
+// `None` while the key is only remembered in A1out.
+type TwoQEntry<V> = Queued<Place, Option<V>>;
+
+/// The full version of 2Q, from "2Q: A Low Overhead High Performance Buffer Management
+/// Replacement Algorithm" by Theodore Johnson and Dennis Shasha. New entries go through the FIFO
+/// A1in, whose evictions are remembered in the FIFO ghost A1out; only keys seen again while in
+/// A1out are admitted to the LRU Am. This is the design S3-FIFO's small, ghost and main queues
+/// follow, with LRU in place of CLOCK-like reinsertion.
+pub struct TwoQCache<K, V> {
+    // A1in, Am and A1out, each with its newest key at the front.
+    queues: Queues<K, Place, Option<V>, 3>,
+    // Sizes of A1in and A1out, called Kin and Kout in the paper.
+    in_size: usize,
+    out_size: usize,
+    resident: usize,
+    capacity: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq, V> TwoQCache<K, V> {
+    /// Sizes A1in at a quarter of the capacity and A1out at half, as the paper recommends.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_queue_sizes(capacity, capacity / 4, capacity / 2)
+    }
+
+    /// A cache of `capacity` whose A1in holds `in_size` and whose A1out remembers `out_size`.
+    pub fn with_queue_sizes(capacity: usize, in_size: usize, out_size: usize) -> Self {
+        Self {
+            queues: Queues::new(),
+            in_size,
+            out_size,
+            resident: 0,
+            capacity,
+            stats: Stats::default(),
+        }
+    }
+
+    fn size(&self, place: Place) -> usize {
+        self.queues.size(place)
+    }
+
+    /// Takes `key` off whichever queue it is on, and forgets it.
+    fn detach(&mut self, key: &K) -> Option<TwoQEntry<V>> {
+        let entry = self.queues.detach(key)?;
+        if entry.value.is_some() {
+            self.resident -= 1;
+        }
+        Some(entry)
+    }
+
+    /// The paper's reclaimfor: until `size` more fits, pages A1in out into A1out while A1in is
+    /// over its size, and evicts from Am otherwise.
+    fn reclaim(&mut self, size: usize) {
+        while self.size(Place::In) + self.size(Place::Main) + size > self.capacity {
+            let from_in = self.size(Place::In) > self.in_size;
+            let key = match (
+                self.queues.oldest(Place::In),
+                self.queues.oldest(Place::Main),
+            ) {
+                (Some(key), _) if from_in => key,
+                (_, Some(key)) => key,
+                (Some(key), None) => key,
+                (None, None) => break,
+            };
+            self.stats.evictions += 1;
+            let Some(entry) = self.detach(&key) else {
+                continue;
+            };
+            if entry.place == Place::In {
+                self.stats.demotions += 1;
+                self.queues.push(key, None, entry.size, Place::Out);
+                while self.size(Place::Out) > self.out_size {
+                    let Some(key) = self.queues.oldest(Place::Out) else {
+                        break;
+                    };
+                    self.detach(&key);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, key: K, value: V, size: usize, place: Place) {
+        self.queues.push(Arc::new(key), Some(value), size, place);
+        self.resident += 1;
+    }
+}
+
+impl<K: Hash + Eq, V> CachePolicy<K, V> for TwoQCache<K, V> {
+    fn insert_with_size(&mut self, key: K, value: V, size: usize) -> Option<V> {
+        if size > self.capacity {
+            return self.remove(&key);
+        }
+        match self.queues.index.get(&key) {
+            Some(entry) if entry.value.is_some() => {
+                let previous = self.queues.resize(&key, size).unwrap().value.replace(value);
+                self.reclaim(0);
+                return previous;
+            }
+            Some(_) => {
+                self.detach(&key);
+                self.stats.ghost_hits += 1;
+                self.reclaim(size);
+                self.push(key, value, size, Place::Main);
+            }
+            None => {
+                self.reclaim(size);
+                self.push(key, value, size, Place::In);
+            }
+        }
+        self.stats.inserts += 1;
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let Some(entry) = self
+            .queues
+            .index
+            .get(key)
+            .filter(|entry| entry.value.is_some())
+        else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        // A1in is a FIFO: correlated references while in it do not count.
+        if entry.place == Place::Main {
+            self.queues.touch(key);
+        }
+        self.peek(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        self.queues.index.get(key)?.value.as_ref()
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.peek(key)?;
+        self.detach(key)?.value
+    }
+
+    fn len(&self) -> usize {
+        self.resident
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admits_to_am_from_a1out() {
+        let mut cache = TwoQCache::with_queue_sizes(4, 1, 2);
+        for k in 1..=4 {
+            cache.insert(k, k);
+        }
+        // Reads in A1in do not keep 1 from being paged out.
+        cache.get(&1);
+        cache.insert(5, 5);
+        assert_eq!(cache.queues.index[&1].place, Place::Out);
+
+        // Seen again while remembered, so it goes to Am.
+        cache.insert(1, 1);
+        assert_eq!(cache.queues.index[&1].place, Place::Main);
+        assert_eq!(cache.queues.index[&2].place, Place::Out);
+
+        // A1out forgets its oldest keys beyond its size.
+        cache.insert(6, 6);
+        cache.insert(7, 7);
+        assert!(!cache.queues.index.contains_key(&2));
+        assert_eq!(cache.size(Place::Out), 2);
+        assert_eq!(cache.stats().ghost_hits, 1);
+    }
+}
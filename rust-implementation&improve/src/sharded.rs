@@ -0,0 +1,154 @@
+//! A thread-safe S3-FIFO built from independently locked shards.
+//!
+//! Every key is hashed to one shard, and each shard is a plain [`S3Fifo`] behind its own mutex,
+//! so threads working on different shards never contend. Eviction decisions are made per shard,
+//! which approximates a single S3-FIFO of the aggregate size as long as keys spread evenly.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::{S3Fifo, Stats};
+
+/// Built with [`S3FifoBuilder::build_sharded`](crate::S3FifoBuilder::build_sharded).
+pub struct ShardedS3Fifo<K: Hash + Eq, V> {
+    shards: Box<[Mutex<S3Fifo<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedS3Fifo<K, V> {
+    pub(crate) fn from_shards(shards: Vec<S3Fifo<K, V>>) -> Self {
+        Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Inserts `value` under `key`, returning the previous value; see [`S3Fifo::insert`].
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).insert(key, value)
+    }
+
+    pub fn insert_with_size(&self, key: K, value: V, size: usize) -> Option<V> {
+        self.shard(&key).insert_with_size(key, value, size)
+    }
+
+    pub fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        self.shard(&key).insert_with_ttl(key, value, ttl)
+    }
+
+    /// Returns a copy of the value for `key`. The shard lock is held only for the lookup.
+    pub fn read(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.shard(key).read(key).cloned()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.shard(key).remove(key)
+    }
+
+    pub fn remove_if(&self, key: &K, predicate: impl FnOnce(&V) -> bool) -> Option<V> {
+        self.shard(key).remove_if(key, predicate)
+    }
+
+    pub fn forget(&self, key: &K) -> Option<V> {
+        self.shard(key).forget(key)
+    }
+
+    /// Keeps only the entries for which `f` returns true, locking one shard at a time.
+    pub fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().retain(&mut f);
+        }
+    }
+
+    /// Drops expired entries from every shard, returning how many were dropped.
+    pub fn purge_expired(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().purge_expired()).sum()
+    }
+
+    /// Stats summed over all shards.
+    pub fn stats(&self) -> Stats {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().stats())
+            .fold(Stats::default(), |total, stats| total + stats)
+    }
+
+    pub fn reset_stats(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().reset_stats();
+        }
+    }
+
+    /// Number of entries resident across all shards.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().unwrap().is_empty())
+    }
+
+    fn shard(&self, key: &K) -> MutexGuard<'_, S3Fifo<K, V>> {
+        let i = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[i].lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use std::thread;
+
+    #[test]
+    fn shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ShardedS3Fifo<String, Vec<u8>>>();
+
+        let cache = S3Fifo::<u32, u32>::builder(440)
+            .small_size(40)
+            .adaptive_small(16, 80)
+            .build_sharded(8)
+            .unwrap();
+        thread::scope(|s| {
+            for t in 0..8 {
+                let cache = &cache;
+                s.spawn(move || {
+                    let mut rng = rand::rngs::StdRng::seed_from_u64(t);
+                    for _ in 0..10_000 {
+                        let k = rng.gen_range(0..1_000);
+                        match rng.gen_range(0..10) {
+                            0..=4 => {
+                                if let Some(v) = cache.read(&k) {
+                                    assert_eq!(v, k);
+                                }
+                            }
+                            5..=8 => {
+                                cache.insert(k, k);
+                            }
+                            _ => {
+                                cache.remove(&k);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert!(cache.len() <= 80 + 400);
+        let stats = cache.stats();
+        assert!(stats.hits > 0 && stats.hits + stats.misses > 30_000);
+        cache.retain(|k, _| k % 2 == 0);
+        assert_eq!(cache.remove_if(&1, |_| true), None);
+        for shard in cache.shards.iter() {
+            let shard = shard.lock().unwrap();
+            assert_eq!(shard.small_size + shard.main_size, 55);
+            assert!(shard.small_weight <= shard.small_size);
+            assert!(shard.main_weight <= shard.main_size);
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! Trace-driven simulation in the manner of libCacheSim's `cachesim`: replays requests through
+//! caches and reports how many of them, and of their bytes, missed.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::policies::{
+    ArcCache, CachePolicy, ClockCache, FifoCache, LirsCache, LruCache, Optimal, SieveCache,
+    SizeAwareOptimal, TwoQCache, WTinyLfuCache,
+};
+use crate::trace::{Op, Request};
+use crate::{ConfigError, S3Fifo};
+
+/// A cache under simulation. Objects are keyed by id and cost their size, but hold no data.
+pub type SimCache = Box<dyn CachePolicy<u64, ()> + Send>;
+
+/// The names [`build_policy`] accepts.
+pub const POLICIES: &[&str] = &[
+    "s3fifo",
+    "fifo",
+    "lru",
+    "clock",
+    "sieve",
+    "arc",
+    "2q",
+    "lirs",
+    "tinylfu",
+    "belady",
+    "beladysize",
+];
+
+/// Why a simulation could not be set up.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimError {
+    /// No policy goes by this name; see [`POLICIES`].
+    UnknownPolicy(String),
+    /// A cache size is neither a whole size nor a fraction of the working set.
+    InvalidCacheSize(String),
+    /// The policy refused the cache size.
+    Config(ConfigError),
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::UnknownPolicy(name) => {
+                write!(
+                    f,
+                    "unknown algorithm {name:?}, expected one of {}",
+                    POLICIES.join(", ")
+                )
+            }
+            SimError::InvalidCacheSize(size) => write!(f, "invalid cache size {size:?}"),
+            SimError::Config(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for SimError {}
+
+impl From<ConfigError> for SimError {
+    fn from(e: ConfigError) -> Self {
+        SimError::Config(e)
+    }
+}
+
+/// Builds the policy `name`, case-insensitively, with room for `capacity`.
+pub fn build_policy(name: &str, capacity: usize) -> Result<SimCache, SimError> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        // Sizes come with each insert, so `capacity` may be a byte count rather than the
+        // number of entries to allocate for.
+        "s3fifo" => Box::new(S3Fifo::builder(capacity).preallocate(false).build()?),
+        "fifo" => Box::new(FifoCache::new(capacity)),
+        "lru" => Box::new(LruCache::new(capacity)),
+        "clock" => Box::new(ClockCache::new(capacity)),
+        "sieve" => Box::new(SieveCache::new(capacity)),
+        "arc" => Box::new(ArcCache::new(capacity)),
+        "2q" | "twoq" => Box::new(TwoQCache::new(capacity)),
+        "lirs" => Box::new(LirsCache::new(capacity)),
+        "tinylfu" | "wtinylfu" | "w-tinylfu" => Box::new(WTinyLfuCache::new(capacity)),
+        "belady" | "optimal" => Box::new(Optimal::new(capacity)),
+        "beladysize" => Box::new(SizeAwareOptimal::new(capacity)),
+        _ => return Err(SimError::UnknownPolicy(name.to_string())),
+    })
+}
+
+/// Whether the policy `name` must be told when each request's object comes up next, which only
+/// some trace formats record and the rest need [`annotate_next_access`] for.
+pub fn needs_next_access(name: &str) -> bool {
+    matches!(
+        name.to_ascii_lowercase().as_str(),
+        "belady" | "optimal" | "beladysize"
+    )
+}
+
+/// A cache size as given on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheSize {
+    /// A size in bytes, or in objects when sizes are ignored.
+    Absolute(u64),
+    /// A fraction of the trace's working set, in (0, 1].
+    Fraction(f64),
+}
+
+impl CacheSize {
+    /// The size for a trace whose working set is `working_set` bytes, or objects. Fractions
+    /// round down, but never to nothing.
+    pub fn resolve(self, working_set: u64) -> usize {
+        match self {
+            CacheSize::Absolute(size) => size as usize,
+            CacheSize::Fraction(fraction) => ((working_set as f64 * fraction) as usize).max(1),
+        }
+    }
+}
+
+impl FromStr for CacheSize {
+    type Err = SimError;
+
+    /// Parses whole sizes with an optional `KiB`, `MiB`, `GiB` or `TiB` suffix, and fractions
+    /// such as `0.1`.
+    fn from_str(s: &str) -> Result<Self, SimError> {
+        let invalid = || SimError::InvalidCacheSize(s.to_string());
+        if s.contains('.') {
+            return match s.parse::<f64>() {
+                Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => {
+                    Ok(CacheSize::Fraction(fraction))
+                }
+                _ => Err(invalid()),
+            };
+        }
+        let units = [("KiB", 10), ("MiB", 20), ("GiB", 30), ("TiB", 40)];
+        let (digits, shift) = units
+            .iter()
+            .find_map(|&(unit, shift)| Some((s.strip_suffix(unit)?, shift)))
+            .unwrap_or((s, 0));
+        match digits.trim().parse::<u64>() {
+            Ok(size) if size > 0 => size
+                .checked_mul(1 << shift)
+                .map(CacheSize::Absolute)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The distinct objects a trace requests, and their total size at their last request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkingSet {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// Reads `requests` through for their working set, stopping at the first failed read.
+pub fn working_set(
+    requests: impl IntoIterator<Item = io::Result<Request>>,
+) -> io::Result<WorkingSet> {
+    let mut sizes = HashMap::new();
+    for request in requests {
+        let request = request?;
+        sizes.insert(request.obj_id, request.obj_size);
+    }
+    Ok(WorkingSet {
+        objects: sizes.len() as u64,
+        bytes: sizes.values().map(|&size| u64::from(size)).sum(),
+    })
+}
+
+/// Reads `requests` in full, filling in [`Request::next_access_vtime`] for formats that do not
+/// record it. Virtual times are indexes into the result. A request's next access is only known
+/// once it has been read, so unlike the other passes this one holds the whole trace.
+pub fn annotate_next_access(
+    requests: impl IntoIterator<Item = io::Result<Request>>,
+) -> io::Result<Vec<Request>> {
+    let mut annotated: Vec<Request> = Vec::new();
+    let mut last = HashMap::new();
+    for request in requests {
+        let vtime = annotated.len();
+        let request = Request {
+            next_access_vtime: None,
+            ..request?
+        };
+        if let Some(previous) = last.insert(request.obj_id, vtime) {
+            annotated[previous].next_access_vtime = Some(vtime as u64);
+        }
+        annotated.push(request);
+    }
+    Ok(annotated)
+}
+
+/// What a cache made of a trace. Requests and bytes count gets and sets, but not deletes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SimResult {
+    pub requests: u64,
+    pub misses: u64,
+    pub bytes: u64,
+    pub miss_bytes: u64,
+}
+
+impl SimResult {
+    pub fn miss_ratio(&self) -> f64 {
+        ratio(self.misses, self.requests)
+    }
+
+    pub fn byte_miss_ratio(&self) -> f64 {
+        ratio(self.miss_bytes, self.bytes)
+    }
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Replays `requests` through `cache`. Gets and sets alike look the object up and admit it on a
+/// miss, as `cachesim` does, and deletes remove it. Objects are charged their size, or 1 if
+/// `ignore_size`; zero-sized objects are charged 1 either way. TTLs are not simulated.
+pub fn simulate(
+    cache: &mut dyn CachePolicy<u64, ()>,
+    requests: impl IntoIterator<Item = Request>,
+    ignore_size: bool,
+) -> SimResult {
+    let mut result = SimResult::default();
+    for (vtime, request) in requests.into_iter().enumerate() {
+        simulate_one(cache, vtime as u64, &request, ignore_size, &mut result);
+    }
+    result
+}
+
+fn simulate_one(
+    cache: &mut dyn CachePolicy<u64, ()>,
+    vtime: u64,
+    request: &Request,
+    ignore_size: bool,
+    result: &mut SimResult,
+) {
+    if request.op == Op::Delete {
+        cache.remove(&request.obj_id);
+        return;
+    }
+    let size = match ignore_size {
+        true => 1,
+        false => request.obj_size.max(1) as usize,
+    };
+    cache.set_next_access(vtime, request.next_access_vtime);
+    result.requests += 1;
+    result.bytes += size as u64;
+    if cache.get(&request.obj_id).is_none() {
+        result.misses += 1;
+        result.miss_bytes += size as u64;
+        cache.insert_with_size(request.obj_id, (), size);
+    }
+}
+
+/// Requests are handed to workers this many at a time.
+const BATCH_SIZE: usize = 4096;
+
+// Batches a worker may fall behind by before the reader waits for it.
+const BATCHES_IN_FLIGHT: usize = 4;
+
+/// Simulates every combination of some policies and cache sizes in one pass over a trace,
+/// spreading the caches over worker threads. Each worker replays the same batches of requests
+/// through its own caches, so the trace is read, and decompressed, only once.
+pub struct Simulation {
+    algorithms: Vec<String>,
+    capacities: Vec<usize>,
+    // One per algorithm and capacity, algorithm-major.
+    caches: Vec<SimCache>,
+    ignore_size: bool,
+    threads: usize,
+}
+
+impl Simulation {
+    /// Builds a cache for each of `algorithms` at each of `capacities`, failing if any name is
+    /// unknown or any policy refuses its capacity. There is a worker for each available core,
+    /// or each cache if there are fewer.
+    pub fn new<S: AsRef<str>>(algorithms: &[S], capacities: &[usize]) -> Result<Self, SimError> {
+        let mut caches = Vec::with_capacity(algorithms.len() * capacities.len());
+        for algorithm in algorithms {
+            for &capacity in capacities {
+                caches.push(build_policy(algorithm.as_ref(), capacity)?);
+            }
+        }
+        Ok(Self {
+            algorithms: algorithms.iter().map(|a| a.as_ref().to_string()).collect(),
+            capacities: capacities.to_vec(),
+            caches,
+            ignore_size: false,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        })
+    }
+
+    /// Charges every object 1 rather than its size, as [`simulate`] does.
+    pub fn ignore_size(mut self, ignore_size: bool) -> Self {
+        self.ignore_size = ignore_size;
+        self
+    }
+
+    /// Sets the number of worker threads. The reader runs on the calling thread besides.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Replays `requests` through every cache. A failed read stops the simulation, and its
+    /// error is returned once the workers have finished.
+    pub fn run(
+        self,
+        requests: impl IntoIterator<Item = io::Result<Request>>,
+    ) -> io::Result<SimMatrix> {
+        let Simulation {
+            algorithms,
+            capacities,
+            caches,
+            ignore_size,
+            threads,
+        } = self;
+        let threads = threads.min(caches.len()).max(1);
+        // Deal the caches out in turn, so that each worker gets a mix of policies and sizes.
+        let mut shares: Vec<Vec<(usize, SimCache)>> = (0..threads).map(|_| Vec::new()).collect();
+        for (index, cache) in caches.into_iter().enumerate() {
+            shares[index % threads].push((index, cache));
+        }
+
+        let mut results = vec![vec![SimResult::default(); capacities.len()]; algorithms.len()];
+        let read = thread::scope(|s| {
+            let mut senders = Vec::with_capacity(threads);
+            let mut workers = Vec::with_capacity(threads);
+            for share in shares {
+                let (sender, receiver) = mpsc::sync_channel(BATCHES_IN_FLIGHT);
+                senders.push(sender);
+                workers.push(s.spawn(move || replay(share, receiver, ignore_size)));
+            }
+
+            let read = read_batches(requests, |batch| {
+                for sender in &senders {
+                    // Workers only hang up by panicking, which the join below reports.
+                    let _ = sender.send(Arc::clone(&batch));
+                }
+            });
+            drop(senders);
+            for worker in workers {
+                for (index, result) in worker.join().expect("simulation worker panicked") {
+                    results[index / capacities.len()][index % capacities.len()] = result;
+                }
+            }
+            read
+        });
+        read?;
+
+        Ok(SimMatrix {
+            algorithms,
+            capacities,
+            results,
+        })
+    }
+}
+
+/// A batch of requests, and the virtual time of the first.
+type Batch = Arc<(u64, Vec<Request>)>;
+
+/// Reads `requests` in batches, handing each to `send`.
+fn read_batches(
+    requests: impl IntoIterator<Item = io::Result<Request>>,
+    mut send: impl FnMut(Batch),
+) -> io::Result<()> {
+    let mut requests = requests.into_iter();
+    let mut vtime = 0;
+    loop {
+        let batch = requests
+            .by_ref()
+            .take(BATCH_SIZE)
+            .collect::<io::Result<Vec<_>>>()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let len = batch.len();
+        send(Arc::new((vtime, batch)));
+        vtime += len as u64;
+    }
+}
+
+fn replay(
+    mut caches: Vec<(usize, SimCache)>,
+    batches: mpsc::Receiver<Batch>,
+    ignore_size: bool,
+) -> Vec<(usize, SimResult)> {
+    let mut results = vec![SimResult::default(); caches.len()];
+    for batch in batches {
+        let (start, requests) = &*batch;
+        for ((_, cache), result) in caches.iter_mut().zip(&mut results) {
+            for (vtime, request) in (*start..).zip(requests) {
+                simulate_one(cache.as_mut(), vtime, request, ignore_size, result);
+            }
+        }
+    }
+    caches
+        .into_iter()
+        .map(|(index, _)| index)
+        .zip(results)
+        .collect()
+}
+
+/// What each cache of a [`Simulation`] made of the trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimMatrix {
+    pub algorithms: Vec<String>,
+    pub capacities: Vec<usize>,
+    /// Indexed by algorithm, then capacity, in the order they were given.
+    pub results: Vec<Vec<SimResult>>,
+}
+
+impl SimMatrix {
+    /// The results in order, with the algorithm and capacity of each.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize, &SimResult)> {
+        self.algorithms
+            .iter()
+            .zip(&self.results)
+            .flat_map(move |(algorithm, row)| {
+                self.capacities
+                    .iter()
+                    .zip(row)
+                    .map(move |(&capacity, result)| (algorithm.as_str(), capacity, result))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(obj_id: u64, obj_size: u32) -> Request {
+        Request {
+            obj_id,
+            obj_size,
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn cache_sizes() {
+        assert_eq!("1000".parse(), Ok(CacheSize::Absolute(1000)));
+        assert_eq!("4 GiB".parse(), Ok(CacheSize::Absolute(4 << 30)));
+        assert_eq!("0.1".parse(), Ok(CacheSize::Fraction(0.1)));
+        for invalid in ["0", "1.5", "-0.1", "1 GB", "x"] {
+            assert!(invalid.parse::<CacheSize>().is_err(), "{invalid}");
+        }
+        assert_eq!(CacheSize::Fraction(0.1).resolve(1_005), 100);
+        assert_eq!(CacheSize::Fraction(0.001).resolve(10), 1);
+    }
+
+    #[test]
+    fn replays() {
+        let requests =
+            [(1, 100), (2, 300), (1, 100), (3, 100), (2, 300)].map(|(id, size)| request(id, size));
+        assert_eq!(
+            working_set(requests.map(Ok)).unwrap(),
+            WorkingSet {
+                objects: 3,
+                bytes: 500
+            }
+        );
+        let requests = annotate_next_access(requests.map(Ok)).unwrap();
+        let next = requests.iter().map(|r| r.next_access_vtime).collect::<Vec<_>>();
+        assert_eq!(next, [Some(2), Some(4), None, None, None]);
+
+        let mut cache = build_policy("Belady", 400).unwrap();
+        let result = simulate(cache.as_mut(), requests.iter().copied(), false);
+        // 3 is never requested again, so it is not admitted in place of 1 or 2.
+        let expected = SimResult {
+            requests: 5,
+            misses: 3,
+            bytes: 900,
+            miss_bytes: 500,
+        };
+        assert_eq!(result, expected);
+        assert_eq!(result.miss_ratio(), 0.6);
+
+        // Counting objects, 2 and 3 fit together.
+        let mut cache = build_policy("fifo", 2).unwrap();
+        let result = simulate(cache.as_mut(), requests, true);
+        assert_eq!((result.misses, result.bytes), (3, 5));
+    }
+
+    #[test]
+    fn policies() {
+        for name in POLICIES {
+            let mut cache = build_policy(name, 100).unwrap();
+            let requests = (0..1_000).map(|i| request(i % 150, 1));
+            assert!(
+                simulate(cache.as_mut(), requests, false).misses >= 150,
+                "{name}"
+            );
+        }
+        assert!(needs_next_access("Belady") && !needs_next_access("lru"));
+        assert!(matches!(
+            build_policy("mru", 10),
+            Err(SimError::UnknownPolicy(_))
+        ));
+        assert!(matches!(
+            build_policy("s3fifo", 1),
+            Err(SimError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn byte_capacities() {
+        // Nothing may be allocated up front for the capacity, which is in bytes here.
+        for name in POLICIES {
+            let mut cache = build_policy(name, 1 << 30).unwrap();
+            let requests = (0..1_000).map(|i| request(i % 150, 1 << 20));
+            assert!(
+                simulate(cache.as_mut(), requests, false).misses >= 150,
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn runs_in_parallel() {
+        let mut requests = (0..20_000u64)
+            .map(|i| request((i * 7919) % 1_000 % (i % 300 + 1), 1 + (i % 5) as u32))
+            .collect::<Vec<_>>();
+        requests.push(Request {
+            op: Op::Delete,
+            ..requests[0]
+        });
+        let requests = annotate_next_access(requests.into_iter().map(Ok)).unwrap();
+        let algorithms = ["s3fifo", "lru", "belady"];
+        let capacities = [50, 200, 1_000];
+
+        let simulation = Simulation::new(&algorithms, &capacities).unwrap();
+        let matrix = simulation
+            .threads(4)
+            .run(requests.iter().copied().map(Ok))
+            .unwrap();
+        assert_eq!(matrix.results.len(), 3);
+        assert_eq!(matrix.iter().count(), 9);
+        for (algorithm, capacity, result) in matrix.iter() {
+            let mut cache = build_policy(algorithm, capacity).unwrap();
+            let expected = simulate(cache.as_mut(), requests.iter().copied(), false);
+            assert_eq!(*result, expected, "{algorithm} at {capacity}");
+        }
+
+        let failing = [Ok(request(1, 1)), Err(io::ErrorKind::InvalidData.into())];
+        let simulation = Simulation::new(&algorithms, &capacities).unwrap();
+        let err = simulation.run(failing).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(Simulation::new(&["lru", "mru"], &capacities).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Policies for resizing the small queue at run time.
+//!
+//! [`S3Fifo`](crate::S3Fifo) reports what it does to its [`SmallSizePolicy`], and after each
+//! report sets the small queue to the size the policy asks for, clamped to the adaptive bounds
+//! given to [`S3FifoBuilder::adaptive_small`](crate::S3FifoBuilder::adaptive_small).
+
+/// Something the cache did that a sizing policy may react to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SizingEvent {
+    /// A new key was admitted, into main if `ghost_hit` and into small otherwise.
+    Admitted { ghost_hit: bool },
+    /// The value of a resident key was replaced; `in_small` says whether it sat in small.
+    Updated { in_small: bool },
+    /// A key evicted from main was inserted again and went back to main. Reported instead of
+    /// `Admitted`, and only when a main ghost is configured.
+    MainGhostHit,
+}
+
+/// The queue sizes and occupancy at the time of a [`SizingEvent`], in entries or weight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueState {
+    pub small_size: usize,
+    pub small_weight: usize,
+    pub small_min: usize,
+    pub small_max: usize,
+    pub main_size: usize,
+    pub main_weight: usize,
+    /// Weight remembered by the ghost of small and by the ghost of main.
+    pub ghost_weight: usize,
+    pub main_ghost_weight: usize,
+}
+
+/// Decides how large the small queue should be.
+pub trait SmallSizePolicy: Send + Sync {
+    /// Returns the small queue size to use from now on. The cache clamps it to the adaptive
+    /// bounds, so returning `state.small_size` leaves it unchanged.
+    fn on_event(&mut self, event: SizingEvent, state: &QueueState) -> usize;
+
+    /// Whether whatever small gains is taken from main and whatever it loses is given to main,
+    /// holding the total capacity constant, as every policy here does. A policy that returns
+    /// false leaves main at its size instead, so the cache's
+    /// [`capacity`](crate::S3Fifo::capacity) grows and shrinks with small.
+    fn conserves_capacity(&self) -> bool {
+        true
+    }
+
+    /// Whether the policy relies on hits in the ghost of main, so that the builder refuses to
+    /// build without one.
+    fn needs_main_ghost(&self) -> bool {
+        false
+    }
+}
+
+/// Never resizes the small queue. This is S3-FIFO as described in the paper, and the default
+/// unless adaptive bounds are configured.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StaticPolicy;
+
+impl SmallSizePolicy for StaticPolicy {
+    fn on_event(&mut self, _: SizingEvent, state: &QueueState) -> usize {
+        state.small_size
+    }
+}
+
+/// Every three admissions, grows small by one when it is full, or else shrinks it by one when a
+/// key resident in small was updated in the meantime. The default with adaptive bounds. Capacity
+/// moves between the two queues, keeping their total constant.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeuristicPolicy {
+    insert_count: usize,  // 跟踪插入次数
+    small_operated: bool, // 标记自上次调整以来是否有操作发生在small队列上
+}
+
+impl HeuristicPolicy {
+    // 定义何时增加small队列大小的条件
+    fn should_increase_small(state: &QueueState) -> bool {
+        state.small_weight >= state.small_size
+    }
+
+    // 定义何时减少small队列大小的条件
+    fn should_decrease_small(&self, state: &QueueState) -> bool {
+        self.small_operated && state.small_size > state.small_min
+    }
+}
+
+impl SmallSizePolicy for HeuristicPolicy {
+    fn on_event(&mut self, event: SizingEvent, state: &QueueState) -> usize {
+        match event {
+            SizingEvent::Updated { in_small } => {
+                self.small_operated |= in_small;
+                state.small_size
+            }
+            SizingEvent::Admitted { .. } | SizingEvent::MainGhostHit => {
+                self.insert_count += 1;
+                // 每三次插入操作后，检查是否需要调整队列大小
+                if self.insert_count < 3 {
+                    return state.small_size;
+                }
+                let size = if Self::should_increase_small(state) {
+                    state.small_size + 1
+                } else if self.should_decrease_small(state) {
+                    state.small_size - 1
+                } else {
+                    state.small_size
+                };
+                self.insert_count = 0; // 重置插入计数
+                self.small_operated = false; // 重置操作标记
+                size
+            }
+        }
+    }
+}
+
+/// Sizes small by how often keys come back from the ghost. Each ghost hit is a key that small
+/// let go before it was accessed a second time, so after every `window` admissions small grows
+/// when the share of ghost hits was above `grow_above`, and shrinks when it was below
+/// `shrink_below`. Each step is a sixteenth of the adaptive range, taken from or given to main.
+#[derive(Clone, Copy, Debug)]
+pub struct GhostHitPolicy {
+    window: usize,
+    grow_above: f64,
+    shrink_below: f64,
+    admissions: usize,
+    ghost_hits: usize,
+}
+
+impl GhostHitPolicy {
+    /// Re-evaluates every `window` admissions, growing above 5% ghost hits and shrinking below 1%.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            grow_above: 0.05,
+            shrink_below: 0.01,
+            admissions: 0,
+            ghost_hits: 0,
+        }
+    }
+
+    pub fn with_thresholds(mut self, grow_above: f64, shrink_below: f64) -> Self {
+        self.grow_above = grow_above;
+        self.shrink_below = shrink_below;
+        self
+    }
+}
+
+impl Default for GhostHitPolicy {
+    fn default() -> Self {
+        Self::new(1_000)
+    }
+}
+
+impl SmallSizePolicy for GhostHitPolicy {
+    fn on_event(&mut self, event: SizingEvent, state: &QueueState) -> usize {
+        let ghost_hit = match event {
+            SizingEvent::Admitted { ghost_hit } => ghost_hit,
+            SizingEvent::MainGhostHit => false,
+            SizingEvent::Updated { .. } => return state.small_size,
+        };
+        self.admissions += 1;
+        self.ghost_hits += usize::from(ghost_hit);
+        if self.admissions < self.window {
+            return state.small_size;
+        }
+        let ratio = self.ghost_hits as f64 / self.admissions as f64;
+        self.admissions = 0;
+        self.ghost_hits = 0;
+        let step = ((state.small_max - state.small_min) / 16).max(1);
+        if ratio > self.grow_above {
+            state.small_size + step
+        } else if ratio < self.shrink_below {
+            state.small_size.saturating_sub(step)
+        } else {
+            state.small_size
+        }
+    }
+}
+
+/// Sizes small the way ARC sizes its recency list. A hit in the ghost of small is a key small
+/// let go too early, so small grows; a hit in the ghost of main is a key main let go too early,
+/// so small shrinks. Capacity moves between the two queues, keeping their total constant.
+///
+/// Each step is `unit` times the ratio of the other ghost's weight to the hit ghost's, and at
+/// least `unit`, so the side whose ghost is hit less often gives way faster. Small could only
+/// ever grow without a [main ghost](crate::S3FifoBuilder::main_ghost_capacity), so the builder
+/// requires one.
+#[derive(Clone, Copy, Debug)]
+pub struct GhostFeedbackPolicy {
+    unit: usize,
+}
+
+impl GhostFeedbackPolicy {
+    /// Moves capacity in multiples of `unit`: 1 for entry counts, or e.g. a typical entry's size
+    /// in weighted mode.
+    pub fn new(unit: usize) -> Self {
+        Self { unit: unit.max(1) }
+    }
+
+    fn step(&self, hit_ghost: usize, other_ghost: usize) -> usize {
+        self.unit * (other_ghost / hit_ghost.max(1)).max(1)
+    }
+}
+
+impl Default for GhostFeedbackPolicy {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl SmallSizePolicy for GhostFeedbackPolicy {
+    fn on_event(&mut self, event: SizingEvent, state: &QueueState) -> usize {
+        match event {
+            SizingEvent::Admitted { ghost_hit: true } => {
+                state.small_size + self.step(state.ghost_weight, state.main_ghost_weight)
+            }
+            SizingEvent::MainGhostHit => state
+                .small_size
+                .saturating_sub(self.step(state.main_ghost_weight, state.ghost_weight)),
+            _ => state.small_size,
+        }
+    }
+
+    fn needs_main_ghost(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATE: QueueState = QueueState {
+        small_size: 10,
+        small_weight: 5,
+        small_min: 2,
+        small_max: 34,
+        main_size: 90,
+        main_weight: 90,
+        ghost_weight: 20,
+        main_ghost_weight: 50,
+    };
+
+    #[test]
+    fn heuristic() {
+        let mut policy = HeuristicPolicy::default();
+        assert!(policy.conserves_capacity());
+        let admit = SizingEvent::Admitted { ghost_hit: false };
+        let full = QueueState {
+            small_weight: 10,
+            ..STATE
+        };
+        assert_eq!(policy.on_event(admit, &full), 10);
+        assert_eq!(policy.on_event(admit, &full), 10);
+        assert_eq!(policy.on_event(admit, &full), 11);
+
+        assert_eq!(policy.on_event(SizingEvent::Updated { in_small: true }, &STATE), 10);
+        assert_eq!(policy.on_event(admit, &STATE), 10);
+        assert_eq!(policy.on_event(admit, &STATE), 10);
+        assert_eq!(policy.on_event(admit, &STATE), 9);
+        for _ in 0..3 {
+            assert_eq!(policy.on_event(admit, &STATE), 10);
+        }
+    }
+
+    #[test]
+    fn ghost_hits() {
+        let mut policy = GhostHitPolicy::new(10);
+        assert!(policy.conserves_capacity());
+        for i in 0..10 {
+            let size = policy.on_event(SizingEvent::Admitted { ghost_hit: i == 0 }, &STATE);
+            assert_eq!(size, if i == 9 { 12 } else { 10 });
+        }
+        for i in 0..10 {
+            let size = policy.on_event(SizingEvent::Admitted { ghost_hit: false }, &STATE);
+            assert_eq!(size, if i == 9 { 8 } else { 10 });
+        }
+        assert_eq!(StaticPolicy.on_event(SizingEvent::Admitted { ghost_hit: true }, &STATE), 10);
+    }
+
+    #[test]
+    fn ghost_feedback() {
+        let mut policy = GhostFeedbackPolicy::new(2);
+        assert!(policy.conserves_capacity());
+        // 50 / 20 rounds down to a ratio of 2, and 20 / 50 up to the minimum of 1.
+        assert_eq!(policy.on_event(SizingEvent::Admitted { ghost_hit: true }, &STATE), 14);
+        assert_eq!(policy.on_event(SizingEvent::MainGhostHit, &STATE), 8);
+        assert_eq!(policy.on_event(SizingEvent::Admitted { ghost_hit: false }, &STATE), 10);
+        let no_main_ghost = QueueState {
+            main_ghost_weight: 0,
+            ..STATE
+        };
+        assert_eq!(policy.on_event(SizingEvent::MainGhostHit, &no_main_ghost), 0);
+    }
+}
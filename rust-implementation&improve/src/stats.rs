@@ -0,0 +1,135 @@
+//! Hit/miss and queue-movement counters.
+
+use std::ops::Add;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// A snapshot of what a cache has done since it was created or its stats were last reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Reads that found a live entry.
+    pub hits: u64,
+    /// Reads that found nothing, or only an expired entry.
+    pub misses: u64,
+    /// Keys admitted to the cache, whether into small or, on a ghost hit, into main. Updates of
+    /// resident keys are not counted.
+    pub inserts: u64,
+    /// Entries moved from small to main because they were accessed while in small.
+    pub promotions: u64,
+    /// Entries dropped from small into the ghost.
+    pub demotions: u64,
+    /// Inserts of keys found in the ghost, which go straight to main.
+    pub ghost_hits: u64,
+    /// Times the tail of main was accessed and put back at its head instead of being evicted.
+    pub reinsertions: u64,
+    /// Entries that left the cache for lack of space, from either small or main.
+    pub evictions: u64,
+    /// Access counts of entries as they came up at the tail of small or main: index `n` counts
+    /// those read `n` times, and the last index those read as often or more. Every promotion,
+    /// demotion, reinsertion and eviction from main is counted, so an entry reinserted twice and
+    /// then evicted is counted three times. Evictions alone would only ever show counts below
+    /// the promotion threshold, whereas this shows how the frequency cap and threshold fit the
+    /// workload.
+    pub tail_freqs: [u64; FREQ_BUCKETS],
+}
+
+/// Number of buckets in [`Stats::tail_freqs`].
+pub const FREQ_BUCKETS: usize = 8;
+
+impl Stats {
+    /// Fraction of reads that were hits, or 0 if there were no reads.
+    pub fn hit_ratio(&self) -> f64 {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            0.0
+        } else {
+            self.hits as f64 / reads as f64
+        }
+    }
+
+    /// Fraction of admissions that were ghost hits, or 0 if nothing was admitted. High values
+    /// suggest small is too small; values near 0 with a large ghost suggest the ghost is.
+    pub fn ghost_hit_ratio(&self) -> f64 {
+        if self.inserts == 0 {
+            0.0
+        } else {
+            self.ghost_hits as f64 / self.inserts as f64
+        }
+    }
+}
+
+impl Add for Stats {
+    type Output = Stats;
+
+    fn add(self, other: Stats) -> Stats {
+        Stats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            inserts: self.inserts + other.inserts,
+            promotions: self.promotions + other.promotions,
+            demotions: self.demotions + other.demotions,
+            ghost_hits: self.ghost_hits + other.ghost_hits,
+            reinsertions: self.reinsertions + other.reinsertions,
+            evictions: self.evictions + other.evictions,
+            tail_freqs: std::array::from_fn(|i| {
+                self.tail_freqs[i] + other.tail_freqs[i]
+            }),
+        }
+    }
+}
+
+/// The live counters behind [`Stats`]. They are atomics so that `&self` reads can count hits.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub inserts: AtomicU64,
+    pub promotions: AtomicU64,
+    pub demotions: AtomicU64,
+    pub ghost_hits: AtomicU64,
+    pub reinsertions: AtomicU64,
+    pub evictions: AtomicU64,
+    pub tail_freqs: [AtomicU64; FREQ_BUCKETS],
+}
+
+impl Counters {
+    pub fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Relaxed);
+    }
+
+    pub fn record_freq(&self, freq: u8) {
+        Self::bump(&self.tail_freqs[usize::from(freq).min(FREQ_BUCKETS - 1)]);
+    }
+
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            hits: self.hits.load(Relaxed),
+            misses: self.misses.load(Relaxed),
+            inserts: self.inserts.load(Relaxed),
+            promotions: self.promotions.load(Relaxed),
+            demotions: self.demotions.load(Relaxed),
+            ghost_hits: self.ghost_hits.load(Relaxed),
+            reinsertions: self.reinsertions.load(Relaxed),
+            evictions: self.evictions.load(Relaxed),
+            tail_freqs: std::array::from_fn(|i| self.tail_freqs[i].load(Relaxed)),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.hits,
+            &self.misses,
+            &self.inserts,
+            &self.promotions,
+            &self.demotions,
+            &self.ghost_hits,
+            &self.reinsertions,
+            &self.evictions,
+        ]
+        .into_iter()
+        .chain(&self.tail_freqs)
+        {
+            counter.store(0, Relaxed);
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! Block I/O traces: MSR Cambridge's CSV and VMware's binary vscsi. Each I/O is a byte range,
+//! which is either one object keyed by its offset, or split into fixed-size blocks keyed by
+//! block number, as a block cache would see it.
+
+use std::io::{self, BufRead, ErrorKind};
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::path::Path;
+
+use super::text::{column, fields, number, Lines};
+use super::{read_record, truncated, Op, Request, TraceInput};
+
+/// How to turn block I/Os into requests.
+#[derive(Clone, Debug, Default)]
+pub struct BlockOptions {
+    /// Splits each I/O into requests for the blocks of this many bytes it touches. Without it,
+    /// an I/O is a single request, keyed by its offset and sized by its length.
+    pub block_size: Option<NonZeroU32>,
+}
+
+/// Yields the requests for one I/O after another.
+struct Splitter {
+    block_size: Option<NonZeroU32>,
+    template: Request,
+    // Ids still to yield for the current I/O.
+    ids: Range<u64>,
+}
+
+impl Splitter {
+    fn new(options: BlockOptions) -> Self {
+        Self {
+            block_size: options.block_size,
+            template: Request::default(),
+            ids: 0..0,
+        }
+    }
+
+    /// Starts on an I/O of `request.obj_size` bytes at `offset`, unless it runs past the end of
+    /// a 64-bit address space.
+    fn split(&mut self, offset: u64, request: Request) -> Result<(), String> {
+        let size = request.obj_size;
+        let end = offset
+            .checked_add(u64::from(size.max(1)))
+            .ok_or_else(|| format!("I/O of {size} bytes at {offset} is out of range"))?;
+        let (ids, obj_size) = match self.block_size {
+            None => (offset..offset + 1, size),
+            Some(block_size) => {
+                let block = u64::from(block_size.get());
+                (offset / block..(end - 1) / block + 1, block_size.get())
+            }
+        };
+        self.template = Request {
+            obj_size,
+            ..request
+        };
+        self.ids = ids;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Option<Request> {
+        self.ids.next().map(|obj_id| Request {
+            obj_id,
+            ..self.template
+        })
+    }
+}
+
+/// Reads the MSR Cambridge traces, whose lines are
+/// `timestamp,hostname,disk number,type,offset,size,response time` with timestamps in Windows
+/// filetime ticks of 100ns. Reads are gets and writes are sets. Keys do not include the host or
+/// disk, so traces of several disks should be read separately.
+pub struct MsrReader<R> {
+    lines: Lines<R>,
+    splitter: Splitter,
+    start: Option<u64>,
+}
+
+impl MsrReader<TraceInput> {
+    pub fn open(path: impl AsRef<Path>, options: BlockOptions) -> io::Result<Self> {
+        super::open(path).map(|input| Self::new(input, options))
+    }
+}
+
+impl<R: BufRead> MsrReader<R> {
+    pub fn new(reader: R, options: BlockOptions) -> Self {
+        Self {
+            lines: Lines::new(reader),
+            splitter: Splitter::new(options),
+            start: None,
+        }
+    }
+
+    fn parse(line: &str, start: &mut Option<u64>) -> Result<(u64, Request), String> {
+        let fields = fields(line, ',');
+        let ticks: u64 = number(column(&fields, 0, "timestamp")?, "timestamp")?;
+        let op = match column(&fields, 3, "type")? {
+            kind if kind.eq_ignore_ascii_case("read") => Op::Get,
+            kind if kind.eq_ignore_ascii_case("write") => Op::Set,
+            kind => return Err(format!("unknown type {kind:?}")),
+        };
+        let offset = number(column(&fields, 4, "offset")?, "offset")?;
+        let start = *start.get_or_insert(ticks);
+        let request = Request {
+            timestamp: (ticks.saturating_sub(start) / 10_000_000) as u32,
+            obj_size: number(column(&fields, 5, "size")?, "size")?,
+            op,
+            ..Request::default()
+        };
+        Ok((offset, request))
+    }
+}
+
+impl<R: BufRead> Iterator for MsrReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(request) = self.splitter.next() {
+                return Some(Ok(request));
+            }
+            let parsed = match self.lines.next_line()? {
+                Ok(line) => Self::parse(line, &mut self.start),
+                Err(e) => return Some(Err(e)),
+            };
+            let split = parsed.and_then(|(offset, request)| self.splitter.split(offset, request));
+            if let Err(message) = split {
+                return Some(Err(self.lines.fail(message)));
+            }
+        }
+    }
+}
+
+// Logical block numbers in vscsi traces count sectors of this many bytes.
+const SECTOR_SIZE: u64 = 512;
+
+/// The two vscsi record layouts, told apart by the version field in the first record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VscsiVersion {
+    /// `u32 serial, u32 length, u32 scatter-gather count, u16 command, u16 version, u64 lbn,
+    /// u64 timestamp`
+    V1,
+    /// `u16 command, u16 version, u32 serial, u32 length, u32 scatter-gather count, u64 lbn,
+    /// u64 timestamp, u64 response time`
+    V2,
+}
+
+impl VscsiVersion {
+    fn detect(head: &[u8]) -> Option<Self> {
+        // The version is in the high byte of a little-endian u16.
+        match (head.get(3), head.get(15)) {
+            (Some(2), _) => Some(VscsiVersion::V2),
+            (_, Some(1)) => Some(VscsiVersion::V1),
+            _ => None,
+        }
+    }
+
+    fn record_size(self) -> usize {
+        match self {
+            VscsiVersion::V1 => 32,
+            VscsiVersion::V2 => 40,
+        }
+    }
+
+    /// The command, length, lbn and timestamp of a record.
+    fn decode(self, record: &[u8]) -> (u16, u32, u64, u64) {
+        let u16_at = |at: usize| u16::from_le_bytes(record[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(record[at..at + 8].try_into().unwrap());
+        match self {
+            VscsiVersion::V1 => (u16_at(12), u32_at(4), u64_at(16), u64_at(24)),
+            VscsiVersion::V2 => (u16_at(0), u32_at(8), u64_at(16), u64_at(24)),
+        }
+    }
+}
+
+/// Reads VMware vscsi traces of either version, with timestamps in microseconds. SCSI reads are
+/// gets, writes are sets, and other commands are skipped.
+pub struct VscsiReader<R> {
+    reader: R,
+    version: Option<VscsiVersion>,
+    splitter: Splitter,
+    start: Option<u64>,
+    done: bool,
+}
+
+impl VscsiReader<TraceInput> {
+    pub fn open(path: impl AsRef<Path>, options: BlockOptions) -> io::Result<Self> {
+        super::open(path).map(|input| Self::new(input, options))
+    }
+}
+
+impl<R: BufRead> VscsiReader<R> {
+    pub fn new(reader: R, options: BlockOptions) -> Self {
+        Self {
+            reader,
+            version: None,
+            splitter: Splitter::new(options),
+            start: None,
+            done: false,
+        }
+    }
+
+    /// Reads up to the next read or write, returning its offset and request.
+    fn read(&mut self) -> io::Result<Option<(u64, Request)>> {
+        let version = match self.version {
+            Some(version) => version,
+            None => {
+                let head = self.reader.fill_buf()?;
+                if head.is_empty() {
+                    return Ok(None);
+                }
+                let version = VscsiVersion::detect(head).ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        "not a vscsi trace of version 1 or 2",
+                    )
+                })?;
+                *self.version.insert(version)
+            }
+        };
+        let mut record = [0; 40];
+        let record = &mut record[..version.record_size()];
+        loop {
+            match read_record(&mut self.reader, record)? {
+                0 => return Ok(None),
+                len if len < record.len() => return Err(truncated(len)),
+                _ => {}
+            }
+            let (command, len, lbn, micros) = version.decode(record);
+            let op = match command {
+                0x08 | 0x28 | 0xa8 | 0x88 => Op::Get,
+                0x0a | 0x2a | 0xaa | 0x8a => Op::Set,
+                _ => continue,
+            };
+            let start = *self.start.get_or_insert(micros);
+            let request = Request {
+                timestamp: (micros.saturating_sub(start) / 1_000_000) as u32,
+                obj_size: len,
+                op,
+                ..Request::default()
+            };
+            let offset = lbn.checked_mul(SECTOR_SIZE).ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidData, format!("lbn {lbn} is out of range"))
+            })?;
+            return Ok(Some((offset, request)));
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for VscsiReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(request) = self.splitter.next() {
+                return Some(Ok(request));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read() {
+                Ok(Some((offset, request))) => {
+                    if let Err(message) = self.splitter.split(offset, request) {
+                        self.done = true;
+                        return Some(Err(io::Error::new(ErrorKind::InvalidData, message)));
+                    }
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(requests: impl Iterator<Item = io::Result<Request>>) -> Vec<(u32, u64, u32, Op)> {
+        requests
+            .map(|r| r.unwrap())
+            .map(|r| (r.timestamp, r.obj_id, r.obj_size, r.op))
+            .collect()
+    }
+
+    #[test]
+    fn msr() {
+        let trace = "128166372003061629,hm,1,Read,4096,8192,100\n\
+                     128166372103061629,hm,1,Write,12288,1,50\n";
+        let read = MsrReader::new(trace.as_bytes(), BlockOptions::default());
+        assert_eq!(
+            summary(read),
+            [(0, 4096, 8192, Op::Get), (10, 12288, 1, Op::Set)]
+        );
+
+        let options = BlockOptions {
+            block_size: NonZeroU32::new(4096),
+        };
+        let read = MsrReader::new(trace.as_bytes(), options.clone());
+        assert_eq!(
+            summary(read),
+            [
+                (0, 1, 4096, Op::Get),
+                (0, 2, 4096, Op::Get),
+                (10, 3, 4096, Op::Set)
+            ]
+        );
+
+        // An I/O that runs past the end of the address space is an error.
+        let trace = format!(
+            "128166372003061629,hm,1,Read,{},8192,100\n",
+            u64::MAX - 4095
+        );
+        let mut read = MsrReader::new(trace.as_bytes(), options);
+        assert_eq!(
+            read.next().unwrap().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(read.next().is_none());
+    }
+
+    #[test]
+    fn vscsi() {
+        // A read, an inquiry and a write, 2.5s apart, in each layout.
+        let ios = [
+            (0x28u16, 8192u32, 2u64, 1_000_000u64),
+            (0x12, 36, 0, 3_500_000),
+            (0x2a, 512, 16, 3_500_000),
+        ];
+        let mut v1 = Vec::new();
+        let mut v2 = Vec::new();
+        for (serial, (command, len, lbn, micros)) in ios.into_iter().enumerate() {
+            v1.extend((serial as u32).to_le_bytes());
+            v1.extend(len.to_le_bytes());
+            v1.extend(1u32.to_le_bytes());
+            v1.extend(command.to_le_bytes());
+            v1.extend(0x0100u16.to_le_bytes());
+            v1.extend(lbn.to_le_bytes());
+            v1.extend(micros.to_le_bytes());
+
+            v2.extend(command.to_le_bytes());
+            v2.extend(0x0200u16.to_le_bytes());
+            v2.extend((serial as u32).to_le_bytes());
+            v2.extend(len.to_le_bytes());
+            v2.extend(1u32.to_le_bytes());
+            v2.extend(lbn.to_le_bytes());
+            v2.extend(micros.to_le_bytes());
+            v2.extend(250u64.to_le_bytes());
+        }
+        let expected = [(0, 1024, 8192, Op::Get), (2, 8192, 512, Op::Set)];
+        for trace in [&v1, &v2] {
+            let read = VscsiReader::new(&trace[..], BlockOptions::default());
+            assert_eq!(summary(read), expected);
+        }
+
+        // An lbn whose byte offset does not fit in 64 bits is an error, not a wrapped key.
+        let mut overflow = v2[..40].to_vec();
+        overflow[16..24].copy_from_slice(&(u64::MAX / 256).to_le_bytes());
+        let mut reader = VscsiReader::new(&overflow[..], BlockOptions::default());
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(reader.next().is_none());
+
+        let mut reader = VscsiReader::new(&v2[..v2.len() - 1], BlockOptions::default());
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(reader.next().is_none());
+    }
+}
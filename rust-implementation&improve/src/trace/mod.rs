@@ -0,0 +1,171 @@
+//! Readers for the request traces the paper's simulations replay.
+//!
+//! Readers are iterators of `io::Result<Request>`, so a trace can be streamed through a cache
+//! without loading it whole. A malformed or truncated record ends the trace with an
+//! [`io::ErrorKind::InvalidData`] error.
+//!
+//! Trace files opened by path may be zstd-compressed, as the published traces are, and are then
+//! decompressed as they are read.
+//!
+//! [`io::ErrorKind::InvalidData`]: std::io::ErrorKind::InvalidData
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+
+mod block;
+mod oracle;
+mod text;
+
+pub use block::{BlockOptions, MsrReader, VscsiReader};
+pub use oracle::{OracleGeneralMmap, OracleGeneralReader, ORACLE_GENERAL_RECORD_SIZE};
+pub use text::{
+    CsvOptions, CsvReader, TextOptions, TextReader, TwitterOptions, TwitterReader, TwitterSize,
+};
+
+/// The first four bytes of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// A trace file's contents, decompressed if need be.
+pub type TraceInput = Box<dyn BufRead + Send>;
+
+/// Opens a trace file, streaming it through a zstd decoder if it is compressed. Compression is
+/// recognised by the zstd magic number rather than the `.zst` extension, so misnamed files work
+/// too.
+pub fn open(path: impl AsRef<Path>) -> io::Result<TraceInput> {
+    let mut file = BufReader::new(File::open(path)?);
+    if !is_zstd(&mut file)? {
+        return Ok(Box::new(file));
+    }
+    let decoder = zstd::Decoder::with_buffer(file)?;
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+/// Whether `reader` starts with a zstd frame, without consuming anything.
+fn is_zstd(reader: &mut impl BufRead) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(&ZSTD_MAGIC))
+}
+
+/// Fills `record` from `reader`, returning how much of it was filled: all of it, or less at the
+/// end of the input.
+fn read_record(reader: &mut impl Read, record: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < record.len() {
+        match reader.read(&mut record[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+fn truncated(len: usize) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("trace ends in a partial record of {len} bytes"),
+    )
+}
+
+/// One request of a trace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Request {
+    /// When the request was made, in seconds from the start of the trace.
+    pub timestamp: u32,
+    pub obj_id: u64,
+    pub obj_size: u32,
+    /// Index of the next request for the same object, if there is one. Offline policies such as
+    /// [`Optimal`](crate::policies::Optimal) need it.
+    pub next_access_vtime: Option<u64>,
+    /// What the request does. Formats that do not record it only have gets.
+    pub op: Op,
+    /// How many seconds a set allows the object to be cached for, if it is limited.
+    pub ttl: Option<u32>,
+}
+
+/// The operation a [`Request`] makes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Op {
+    /// A read, which a cache serves or misses.
+    #[default]
+    Get,
+    /// A write of the whole object, which a cache admits.
+    Set,
+    /// An invalidation.
+    Delete,
+}
+
+/// A trace in any of the supported formats, for code that replays traces without caring which.
+pub trait TraceReader: Iterator<Item = io::Result<Request>> + Send {}
+
+impl<T: Iterator<Item = io::Result<Request>> + Send> TraceReader for T {}
+
+/// The trace formats [`open_trace`] can read, with their options.
+#[derive(Clone, Debug)]
+pub enum TraceFormat {
+    OracleGeneral,
+    Csv(CsvOptions),
+    Text(TextOptions),
+    Twitter(TwitterOptions),
+    Msr(BlockOptions),
+    Vscsi(BlockOptions),
+}
+
+/// Opens the trace at `path` with the reader for `format`, decompressing it if need be.
+pub fn open_trace(path: impl AsRef<Path>, format: TraceFormat) -> io::Result<Box<dyn TraceReader>> {
+    let input = open(path)?;
+    Ok(match format {
+        TraceFormat::OracleGeneral => Box::new(OracleGeneralReader::new(input)),
+        TraceFormat::Csv(options) => Box::new(CsvReader::new(input, options)),
+        TraceFormat::Text(options) => Box::new(TextReader::new(input, options)),
+        TraceFormat::Twitter(options) => Box::new(TwitterReader::new(input, options)),
+        TraceFormat::Msr(options) => Box::new(MsrReader::new(input, options)),
+        TraceFormat::Vscsi(options) => Box::new(VscsiReader::new(input, options)),
+    })
+}
+
+/// How the text formats turn keys into [`Request::obj_id`]s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyHashing {
+    /// Keys that are unsigned decimal integers are used as they are, and the rest are hashed.
+    /// A hashed key can then collide with a numeric one, which is rarely a concern in practice.
+    #[default]
+    Auto,
+    /// Keys must be unsigned decimal integers, and anything else is a malformed record.
+    Parse,
+    /// All keys are hashed, numeric or not.
+    Hash,
+}
+
+impl KeyHashing {
+    fn id(self, key: &str) -> Result<u64, String> {
+        match self {
+            KeyHashing::Auto => Ok(key.parse().unwrap_or_else(|_| fnv1a(key))),
+            KeyHashing::Parse => key
+                .parse()
+                .map_err(|_| format!("key {key:?} is not a number")),
+            KeyHashing::Hash => Ok(fnv1a(key)),
+        }
+    }
+}
+
+/// 64-bit FNV-1a, so that ids are the same across runs and builds, unlike with `RandomState`.
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_hashing() {
+        assert_eq!(KeyHashing::Auto.id("42"), Ok(42));
+        assert_eq!(KeyHashing::Auto.id("a"), Ok(0xaf63_dc4c_8601_ec8c));
+        assert_eq!(KeyHashing::Hash.id("42"), Ok(fnv1a("42")));
+        assert!(KeyHashing::Parse.id("a").is_err());
+    }
+}
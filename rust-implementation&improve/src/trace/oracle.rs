@@ -0,0 +1,220 @@
+//! libCacheSim's oracleGeneral binary format: fixed-size little-endian records of
+//! `u32 timestamp, u64 obj_id, u32 obj_size, i64 next_access_vtime`, with -1 for no next access.
+
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use super::{is_zstd, read_record, truncated, Op, Request, TraceInput};
+
+/// Size of one oracleGeneral record, in bytes.
+pub const ORACLE_GENERAL_RECORD_SIZE: usize = 24;
+
+fn decode(record: &[u8]) -> Request {
+    let field = |at: usize, len: usize| &record[at..at + len];
+    let next = i64::from_le_bytes(field(16, 8).try_into().unwrap());
+    Request {
+        timestamp: u32::from_le_bytes(field(0, 4).try_into().unwrap()),
+        obj_id: u64::from_le_bytes(field(4, 8).try_into().unwrap()),
+        obj_size: u32::from_le_bytes(field(12, 4).try_into().unwrap()),
+        // -1 marks the last request for an object; other negative values do not occur.
+        next_access_vtime: u64::try_from(next).ok(),
+        op: Op::Get,
+        ttl: None,
+    }
+}
+
+/// Streams oracleGeneral records from any reader. Wrap unbuffered readers in a `BufReader`, or
+/// use [`OracleGeneralReader::open`] for files.
+pub struct OracleGeneralReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl OracleGeneralReader<TraceInput> {
+    /// Opens a trace file, decompressing it as it is read if it is zstd-compressed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        super::open(path).map(Self::new)
+    }
+}
+
+impl<R: Read> OracleGeneralReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            done: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for OracleGeneralReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut record = [0; ORACLE_GENERAL_RECORD_SIZE];
+        match read_record(&mut self.reader, &mut record) {
+            Ok(ORACLE_GENERAL_RECORD_SIZE) => Some(Ok(decode(&record))),
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(len) => {
+                self.done = true;
+                Some(Err(truncated(len)))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Reads oracleGeneral records from a memory-mapped file, leaving the paging to the kernel.
+/// Faster than [`OracleGeneralReader`] for uncompressed traces on local disks, and it knows how
+/// many records there are up front. Compressed traces cannot be mapped, and are refused with
+/// [`ErrorKind::InvalidInput`].
+pub struct OracleGeneralMmap {
+    map: Mmap,
+    position: usize,
+}
+
+impl OracleGeneralMmap {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        if is_zstd(&mut BufReader::new(&file))? {
+            let message = "zstd-compressed traces must be streamed with OracleGeneralReader";
+            return Err(io::Error::new(ErrorKind::InvalidInput, message));
+        }
+        // SAFETY: the map is read-only, and traces are not expected to change while they are
+        // replayed. If one is truncated anyway, reads past its new end fault.
+        let map = unsafe { Mmap::map(&file) }?;
+        Ok(Self { map, position: 0 })
+    }
+
+    /// Number of whole records in the trace, read or not.
+    pub fn len(&self) -> usize {
+        self.map.len() / ORACLE_GENERAL_RECORD_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Iterator for OracleGeneralMmap {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.map.get(self.position..)?;
+        if rest.is_empty() {
+            return None;
+        }
+        if rest.len() < ORACLE_GENERAL_RECORD_SIZE {
+            self.position = self.map.len();
+            return Some(Err(truncated(rest.len())));
+        }
+        self.position += ORACLE_GENERAL_RECORD_SIZE;
+        Some(Ok(decode(&rest[..ORACLE_GENERAL_RECORD_SIZE])))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.map.len() - self.position).div_ceil(ORACLE_GENERAL_RECORD_SIZE);
+        (left, Some(left))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(requests: &[Request]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for request in requests {
+            bytes.extend(request.timestamp.to_le_bytes());
+            bytes.extend(request.obj_id.to_le_bytes());
+            bytes.extend(request.obj_size.to_le_bytes());
+            let next = request.next_access_vtime.map_or(-1, |next| next as i64);
+            bytes.extend(next.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn requests() -> Vec<Request> {
+        (0..100)
+            .map(|i| Request {
+                timestamp: i as u32 / 10,
+                obj_id: i % 7 + (1 << 40),
+                obj_size: 100 + i as u32,
+                next_access_vtime: (i < 93).then_some(i + 7),
+                op: Op::Get,
+                ttl: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_records() {
+        let bytes = encode(&requests());
+        assert_eq!(bytes.len(), 100 * ORACLE_GENERAL_RECORD_SIZE);
+        let read = OracleGeneralReader::new(&bytes[..]).collect::<io::Result<Vec<_>>>();
+        assert_eq!(read.unwrap(), requests());
+
+        // A partial record at the end is an error, after the whole ones.
+        let mut reader = OracleGeneralReader::new(&bytes[..bytes.len() - 1]);
+        assert_eq!(reader.by_ref().take(99).filter(Result::is_ok).count(), 99);
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn mmap() {
+        let path = std::env::temp_dir().join(format!("s3fifo-mmap-{}.bin", std::process::id()));
+        let mut bytes = encode(&requests());
+        bytes.extend([0; 5]);
+        std::fs::write(&path, &bytes).unwrap();
+        let trace = OracleGeneralMmap::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(trace.len(), 100);
+        assert_eq!(trace.size_hint(), (101, Some(101)));
+        let read = trace.collect::<Vec<_>>();
+        let (last, whole) = read.split_last().unwrap();
+        assert_eq!(last.as_ref().unwrap_err().kind(), ErrorKind::InvalidData);
+        let whole = whole
+            .iter()
+            .map(|r| *r.as_ref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(whole, requests());
+    }
+
+    #[test]
+    fn zstd() {
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("s3fifo-plain-{}.bin", std::process::id()));
+        // Detected by content, not by name.
+        let compressed = dir.join(format!("s3fifo-compressed-{}.bin", std::process::id()));
+        let bytes = encode(&requests());
+        std::fs::write(&plain, &bytes).unwrap();
+        std::fs::write(&compressed, zstd::encode_all(&bytes[..], 3).unwrap()).unwrap();
+
+        for path in [&plain, &compressed] {
+            let read = OracleGeneralReader::open(path).unwrap();
+            assert_eq!(read.collect::<io::Result<Vec<_>>>().unwrap(), requests());
+        }
+        let err = OracleGeneralMmap::open(&compressed).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&compressed).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+//! Line-based formats: CSV with configurable columns, one key per line, and Twitter's cache
+//! traces.
+
+use std::io::{self, BufRead, ErrorKind};
+use std::path::Path;
+
+use super::{KeyHashing, Op, Request, TraceInput};
+
+/// Reads a trace a line at a time, skipping blank lines and numbering them for error messages.
+pub(super) struct Lines<R> {
+    reader: R,
+    line: String,
+    number: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            number: 0,
+            done: false,
+        }
+    }
+
+    /// The next line that is not blank, trimmed.
+    pub fn next_line(&mut self) -> Option<io::Result<&str>> {
+        if self.done {
+            return None;
+        }
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(_) => {
+                    self.number += 1;
+                    if !self.line.trim().is_empty() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        Some(Ok(self.line.trim()))
+    }
+
+    /// Ends the trace with an error about the last line read.
+    pub fn fail(&mut self, message: String) -> io::Error {
+        self.done = true;
+        let message = format!("line {}: {message}", self.number);
+        io::Error::new(ErrorKind::InvalidData, message)
+    }
+}
+
+/// Splits `line` on `delimiter`, trimming whitespace and double quotes from the fields. Quoted
+/// fields cannot contain the delimiter.
+pub(super) fn fields(line: &str, delimiter: char) -> Vec<&str> {
+    line.split(delimiter)
+        .map(|field| field.trim().trim_matches('"'))
+        .collect()
+}
+
+pub(super) fn column<'a>(fields: &[&'a str], at: usize, name: &str) -> Result<&'a str, String> {
+    fields
+        .get(at)
+        .copied()
+        .ok_or_else(|| format!("no {name} in column {at}"))
+}
+
+pub(super) fn number<T: std::str::FromStr>(field: &str, name: &str) -> Result<T, String> {
+    field
+        .parse()
+        .map_err(|_| format!("{name} {field:?} is not a number"))
+}
+
+/// Whole seconds from a timestamp that may have a fractional part.
+fn seconds(field: &str) -> Result<u32, String> {
+    match field.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 => Ok(seconds as u32),
+        _ => Err(format!("timestamp {field:?} is not a number of seconds")),
+    }
+}
+
+/// Which columns of a CSV trace hold what. Columns are numbered from 0.
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    /// Whether the first line names the columns, and is to be skipped.
+    pub has_header: bool,
+    pub key_column: usize,
+    pub key_hashing: KeyHashing,
+    /// A column of timestamps in seconds. Without one, all requests are at time 0.
+    pub time_column: Option<usize>,
+    /// A column of object sizes. Without one, every object has `default_size`.
+    pub size_column: Option<usize>,
+    pub default_size: u32,
+}
+
+impl Default for CsvOptions {
+    /// Keys in the first column of a comma-separated file without a header, all of size 1.
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            has_header: false,
+            key_column: 0,
+            key_hashing: KeyHashing::default(),
+            time_column: None,
+            size_column: None,
+            default_size: 1,
+        }
+    }
+}
+
+/// Reads CSV traces, or any other delimited text, with the columns given by [`CsvOptions`].
+pub struct CsvReader<R> {
+    lines: Lines<R>,
+    options: CsvOptions,
+    header_pending: bool,
+}
+
+impl CsvReader<TraceInput> {
+    pub fn open(path: impl AsRef<Path>, options: CsvOptions) -> io::Result<Self> {
+        super::open(path).map(|input| Self::new(input, options))
+    }
+}
+
+impl<R: BufRead> CsvReader<R> {
+    pub fn new(reader: R, options: CsvOptions) -> Self {
+        let header_pending = options.has_header;
+        Self {
+            lines: Lines::new(reader),
+            options,
+            header_pending,
+        }
+    }
+
+    fn parse(line: &str, options: &CsvOptions) -> Result<Request, String> {
+        let fields = fields(line, options.delimiter);
+        let key = column(&fields, options.key_column, "key")?;
+        let timestamp = match options.time_column {
+            Some(at) => seconds(column(&fields, at, "timestamp")?)?,
+            None => 0,
+        };
+        let obj_size = match options.size_column {
+            Some(at) => number(column(&fields, at, "size")?, "size")?,
+            None => options.default_size,
+        };
+        Ok(Request {
+            timestamp,
+            obj_id: options.key_hashing.id(key)?,
+            obj_size,
+            ..Request::default()
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if std::mem::take(&mut self.header_pending) {
+            if let Err(e) = self.lines.next_line()? {
+                return Some(Err(e));
+            }
+        }
+        let parsed = match self.lines.next_line()? {
+            Ok(line) => Self::parse(line, &self.options),
+            Err(e) => return Some(Err(e)),
+        };
+        Some(parsed.map_err(|message| self.lines.fail(message)))
+    }
+}
+
+/// How to read plain-text traces.
+#[derive(Clone, Debug)]
+pub struct TextOptions {
+    pub key_hashing: KeyHashing,
+    /// The size of every object, as the format has none.
+    pub size: u32,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            key_hashing: KeyHashing::default(),
+            size: 1,
+        }
+    }
+}
+
+/// Reads traces of one key per line, with no times or sizes.
+pub struct TextReader<R> {
+    lines: Lines<R>,
+    options: TextOptions,
+}
+
+impl TextReader<TraceInput> {
+    pub fn open(path: impl AsRef<Path>, options: TextOptions) -> io::Result<Self> {
+        super::open(path).map(|input| Self::new(input, options))
+    }
+}
+
+impl<R: BufRead> TextReader<R> {
+    pub fn new(reader: R, options: TextOptions) -> Self {
+        Self {
+            lines: Lines::new(reader),
+            options,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TextReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = match self.lines.next_line()? {
+            Ok(key) => self.options.key_hashing.id(key),
+            Err(e) => return Some(Err(e)),
+        };
+        let request = id.map(|obj_id| Request {
+            obj_id,
+            obj_size: self.options.size,
+            ..Request::default()
+        });
+        Some(request.map_err(|message| self.lines.fail(message)))
+    }
+}
+
+/// What counts towards an object's size in a Twitter trace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TwitterSize {
+    /// Key and value together, as memory is charged in Twemcache.
+    #[default]
+    KeyAndValue,
+    Value,
+}
+
+/// How to read Twitter cache traces.
+#[derive(Clone, Debug, Default)]
+pub struct TwitterOptions {
+    pub key_hashing: KeyHashing,
+    pub size: TwitterSize,
+}
+
+/// Reads the traces Twitter published of its Twemcache clusters, whose lines are
+/// `timestamp,key,key size,value size,client id,operation,TTL`. Operations are mapped to gets,
+/// sets and deletes; a TTL of 0 means none.
+pub struct TwitterReader<R> {
+    lines: Lines<R>,
+    options: TwitterOptions,
+}
+
+impl TwitterReader<TraceInput> {
+    pub fn open(path: impl AsRef<Path>, options: TwitterOptions) -> io::Result<Self> {
+        super::open(path).map(|input| Self::new(input, options))
+    }
+}
+
+impl<R: BufRead> TwitterReader<R> {
+    pub fn new(reader: R, options: TwitterOptions) -> Self {
+        Self {
+            lines: Lines::new(reader),
+            options,
+        }
+    }
+
+    fn parse(line: &str, options: &TwitterOptions) -> Result<Request, String> {
+        let fields = fields(line, ',');
+        if fields.len() != 7 {
+            return Err(format!("expected 7 fields, found {}", fields.len()));
+        }
+        let key_size: u32 = number(fields[2], "key size")?;
+        let value_size: u32 = number(fields[3], "value size")?;
+        let op = match fields[5] {
+            "get" | "gets" => Op::Get,
+            "set" | "add" | "replace" | "cas" | "append" | "prepend" | "incr" | "decr" => Op::Set,
+            "delete" => Op::Delete,
+            other => return Err(format!("unknown operation {other:?}")),
+        };
+        let ttl: u32 = number(fields[6], "TTL")?;
+        Ok(Request {
+            timestamp: number(fields[0], "timestamp")?,
+            obj_id: options.key_hashing.id(fields[1])?,
+            obj_size: match options.size {
+                TwitterSize::KeyAndValue => key_size.saturating_add(value_size),
+                TwitterSize::Value => value_size,
+            },
+            op,
+            ttl: (ttl > 0).then_some(ttl),
+            ..Request::default()
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for TwitterReader<R> {
+    type Item = io::Result<Request>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let parsed = match self.lines.next_line()? {
+            Ok(line) => Self::parse(line, &self.options),
+            Err(e) => return Some(Err(e)),
+        };
+        Some(parsed.map_err(|message| self.lines.fail(message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(timestamp: u32, obj_id: u64, obj_size: u32) -> Request {
+        Request {
+            timestamp,
+            obj_id,
+            obj_size,
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let trace = "time;size;key\n1.5;100;\"7\"\n\n2;200;x\n";
+        let options = CsvOptions {
+            delimiter: ';',
+            has_header: true,
+            key_column: 2,
+            time_column: Some(0),
+            size_column: Some(1),
+            ..CsvOptions::default()
+        };
+        let read = CsvReader::new(trace.as_bytes(), options.clone());
+        let read = read.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(
+            read,
+            [request(1, 7, 100), request(2, 0xaf63_f54c_8602_1707, 200)]
+        );
+
+        // Errors name the line, and end the trace.
+        let options = CsvOptions {
+            key_hashing: KeyHashing::Parse,
+            ..options
+        };
+        let mut reader = CsvReader::new(trace.as_bytes(), options);
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "line 4: key \"x\" is not a number");
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn text() {
+        let options = TextOptions {
+            size: 4096,
+            ..TextOptions::default()
+        };
+        let read = TextReader::new(&b"3\n 5 \n"[..], options);
+        let read = read.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(read, [request(0, 3, 4096), request(0, 5, 4096)]);
+    }
+
+    #[test]
+    fn twitter() {
+        let trace = "0,key1,5,100,1,get,0\n1,key1,5,120,1,set,3600\n4,key1,5,0,2,delete,0\n";
+        let read = TwitterReader::new(trace.as_bytes(), TwitterOptions::default());
+        let read = read.collect::<io::Result<Vec<_>>>().unwrap();
+        let ops = read.iter().map(|r| (r.timestamp, r.op, r.obj_size, r.ttl));
+        let expected = [
+            (0, Op::Get, 105, None),
+            (1, Op::Set, 125, Some(3600)),
+            (4, Op::Delete, 5, None),
+        ];
+        assert!(ops.eq(expected));
+        assert!(read.iter().all(|r| r.obj_id == read[0].obj_id));
+
+        let options = TwitterOptions {
+            size: TwitterSize::Value,
+            ..TwitterOptions::default()
+        };
+        let mut reader = TwitterReader::new(trace.as_bytes(), options);
+        assert_eq!(reader.next().unwrap().unwrap().obj_size, 100);
+
+        let mut reader = TwitterReader::new(&b"0,k,5,100,1,flush,0\n"[..], Default::default());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}